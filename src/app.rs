@@ -1,11 +1,15 @@
 use rand::{SeedableRng as _, Rng as _};
 use rand::rngs::StdRng;
 
-use termion::event::Key;
+use termion::event::{Event, Key};
 
-use std::collections::HashMap;
-use std::io::{Read, Write};
+use serde::Serialize;
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 /// The instruction enum.
 mod instruction;
@@ -20,19 +24,29 @@ mod ui;
 /// Functionality for serializing the grid
 /// and saving it as an image.
 mod export;
+/// An assembler for laying out programs in the grid.
+mod asm;
+/// A legend of category colors for exported images.
+mod legend;
 
 use super::Options;
-use crate::grid::{Grid, Point, ORIGIN, Dir};
-use instruction::Instruction;
+use crate::grid::{Grid, Point, Dir};
+use instruction::{Instruction, Category, Palette};
 use organism::{OrganismCollection, OrganismState, OrganismId};
 use command::{CommandHandler, Args};
-use ui::UI;
+use ui::{Status, UI};
+
+/// Events read from the terminal.
+type InputEvents = Box<dyn Iterator<Item = std::io::Result<Event>>>;
 
 /// General-purpose app error enum.
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum Error {
     BadWidth,
     BadHeight,
+    BadViewWidth,
+    BadViewHeight,
+    UnknownFill(String),
 }
 
 impl Error {
@@ -40,21 +54,62 @@ impl Error {
         match self {
             Error::BadWidth => "Width cannot be 0.".into(),
             Error::BadHeight => "Height cannot be 0.".into(),
+            Error::BadViewWidth => "View width cannot be 0.".into(),
+            Error::BadViewHeight => "View height cannot be 0.".into(),
+            Error::UnknownFill(s) =>
+                format!("Unknown fill '{}'. Expected an instruction or 'random'.", s).into(),
         }
     }
 }
 
+/// The maximum depth to which commands can run other commands.
+const MAX_COMMAND_DEPTH: usize = 16;
+
+/// The maximum number of cycles that can be stepped backwards.
+const STEP_HISTORY_LEN: usize = 64;
+
+/// The approximate number of grid cells that the step history may hold
+/// across all of its snapshots. On large grids, fewer than
+/// `STEP_HISTORY_LEN` cycles can be stepped backwards.
+const STEP_HISTORY_CELLS: usize = 1 << 26;
+
+/// The maximum number of commands whose edits to the grid can be undone.
+const EDIT_HISTORY_LEN: usize = 64;
+
+/// The number of cycles between checks for Esc while running many cycles
+/// in a single command.
+const ABORT_CHECK_INTERVAL: u32 = 1000;
+
+/// The largest frame rate that can be set with `fps`.
+const MAX_FPS: u32 = 100;
+
+/// The largest number of cycles per second that can be set with `cps`.
+const MAX_CPS: u32 = 1_000_000;
+
+/// The number of cycles between flushes of the population log.
+const LOG_FLUSH_INTERVAL: u64 = 100;
+
 /// Rarely- or never- modified configuration information for the app.
+#[derive(Serialize)]
 struct Config {
-    /// The seed for the RNG. This is never changed during execution.
+    /// The seed for the RNG. This is only changed by `reseed`.
     rng_seed: u64,
     /// How many milliseconds to wait between cycles.
     cycle_frequency: u32,
+    /// How many milliseconds to wait between frames.
+    frame_frequency_ms: u32,
+    /// The target number of cycles to run per second. If set, this overrides
+    /// `cycle_frequency`.
+    cycles_per_second: Option<u32>,
     /// The number of cosmic rays per cycle.
     cosmic_ray_rate: u32,
     /// How many cycles to wait between dedup passes. If zero, then never
     /// perform dedup passes.
     dedup_rate: usize,
+    /// The RGB color of each instruction category.
+    palette: Palette,
+    /// Whether to pause when the last organism dies.
+    pause_on_extinction: bool,
 }
 
 impl Config {
@@ -62,8 +117,12 @@ impl Config {
         Self {
             rng_seed,
             cycle_frequency: 100,
+            frame_frequency_ms: 16,
+            cycles_per_second: None,
             cosmic_ray_rate: 0,
             dedup_rate: 0,
+            palette: Category::DEFAULT_PALETTE,
+            pause_on_extinction: false,
         }
     }   
 }
@@ -83,35 +142,90 @@ impl<W: Write> Commands<W> {
             handlers: HashMap::new(),
         };
         result.register_aliases(&["q", "quit"], commands::quit());
+        result.register_aliases(&["q!", "quit!"], commands::force_quit());
         result.register_aliases(&["l", "list"], commands::list());
+        result.register("stats", commands::stats());
+        result.register("log", commands::log());
         result.register("max", commands::max());
         result.register("set-max", commands::set_max());
+        result.register("kill-policy", commands::kill_policy());
         result.register("lifespan", commands::lifespan());
         result.register("set-lifespan", commands::set_lifespan());
         result.register("max-children", commands::max_children());
         result.register("set-max-children", commands::set_max_children());
+        result.register("fuel", commands::fuel());
+        result.register("energy", commands::energy());
         result.register("speed", commands::speed());
+        result.register("cps", commands::cps());
+        result.register("fps", commands::fps());
         result.register("seed", commands::seed());
+        result.register("reseed", commands::reseed());
+        result.register("palette", commands::palette());
+        result.register("info-height", commands::info_height());
+        result.register("view-size", commands::view_size());
         result.register("source", commands::source());
+        result.register("repeat", commands::repeat());
+        result.register("if-empty", commands::if_empty());
+        result.register("if-alive", commands::if_alive());
         result.register("export", commands::export());
+        result.register("export-overlay", commands::export_overlay());
+        result.register("export-view", commands::export_view());
+        result.register("export-ascii", commands::export_ascii());
         result.register("export-gif", commands::export_gif());
+        result.register("export-gif-trail", commands::export_gif_trail());
+        result.register("export-gif-view", commands::export_gif_view());
+        result.register("export-heatmap", commands::export_heatmap());
+        result.register("dump-json", commands::dump_json());
         result.register("write-error-chance", commands::write_error_chance());
         result.register("wall-pierce-chance", commands::wall_pierce_chance());
+        result.register("wrap", commands::wrap());
         result.register("cosmic-ray-rate", commands::cosmic_ray_rate());
+        result.register("mutate", commands::mutate());
+        result.register("mutate-region", commands::mutate_region());
+        result.register("soup", commands::soup());
         result.register_aliases(&["c", "cycle"], commands::cycle());
+        result.register("bench", commands::bench());
+        result.register("profile-phases", commands::profile_phases());
         result.register_aliases(&["p", "pause"], commands::pause());
+        result.register("pause-on-extinction", commands::pause_on_extinction());
+        result.register("pause-at", commands::pause_at());
+        result.register("stop-at", commands::stop_at());
         result.register("move", commands::move_());
         result.register_aliases(&["w", "write"], commands::write());
         result.register("|", commands::insert_line());
+        result.register("block", commands::block());
+        result.register("assemble", commands::assemble());
+        result.register("disasm", commands::disasm());
+        result.register("hexdump", commands::hexdump());
         result.register("byte", commands::byte());
+        result.register("decode", commands::decode());
+        result.register("encode", commands::encode());
+        result.register("undo", commands::undo());
+        result.register("redo", commands::redo());
         result.register("spawn", commands::spawn());
+        result.register("spawn-at", commands::spawn_at());
+        result.register("seed-grid", commands::seed_grid());
+        result.register("clear", commands::clear());
         result.register("dedup", commands::dedup());
         result.register("auto-dedup", commands::auto_dedup());
         result.register_aliases(&["f", "focus"], commands::focus());
+        result.register("name", commands::name());
+        result.register("watch", commands::watch());
+        result.register("unwatch", commands::unwatch());
         result.register_aliases(&["v", "view"], commands::view());
+        result.register("center", commands::center());
+        result.register("inspect", commands::inspect());
+        result.register("minimap", commands::minimap());
+        result.register("overview", commands::overview());
+        result.register("hide", commands::hide());
+        result.register("show", commands::show());
+        result.register("ruler", commands::ruler());
         result.register("ip", commands::move_ip());
         result.register_aliases(&["r", "run"], commands::run());
         result.register("kill", commands::kill());
+        result.register("kill-all", commands::kill_all());
+        result.register("snapshot", commands::snapshot());
+        result.register("restore", commands::restore());
         result
     }
     fn register(&mut self, name: &str, handler: Rc<dyn CommandHandler<W>>) {
@@ -124,6 +238,27 @@ impl<W: Write> Commands<W> {
     }
 }
 
+/// The time spent in each phase of running cycles, measured while the
+/// `profile-phases` command runs.
+#[derive(Default)]
+struct PhaseTimes {
+    organisms: Duration,
+    cosmic_rays: Duration,
+    dedup: Duration,
+    render: Duration,
+}
+
+/// A copy of the simulation state that can be restored later.
+#[derive(Clone)]
+struct Snapshot {
+    total_cycles: u64,
+    cycles_since_dedup: usize,
+    cosmic_ray_rng: StdRng,
+    organisms: OrganismCollection,
+    grid: Grid<StdRng>,
+    focus: Option<OrganismId>,
+}
+
 pub struct AppState<W> {
     /// The total number of cycles that have passed.
     total_cycles: u64,
@@ -139,65 +274,293 @@ pub struct AppState<W> {
     config: Config,
     /// Command-line parsing information.
     commands: Commands<W>,
+    /// The number of commands currently running other commands.
+    command_depth: usize,
     /// UI information.
     ui: UI<W>,
     /// The ID of the organism, if any, that is currently being focused.
     focus: Option<OrganismId>,
+    /// The IDs of organisms whose state is summarized in the status box.
+    watch: Vec<OrganismId>,
+    /// The snapshot saved by the `snapshot` command, if any.
+    snapshot: Option<Snapshot>,
+    /// Snapshots taken before each cycle run manually while paused, oldest
+    /// first.
+    step_history: VecDeque<Snapshot>,
+    /// The edits made to the grid by each command, oldest first. Each edit
+    /// records the position of a cell and its previous value.
+    edit_history: VecDeque<Vec<(Point, u8)>>,
+    /// Groups of edits that have been undone, in the same format as
+    /// `edit_history`, most recently undone last.
+    redo_history: Vec<Vec<(Point, u8)>>,
+    /// The edits made so far by the command currently running, if any.
+    pending_edits: Option<Vec<(Point, u8)>>,
+    /// The CSV file, if any, to which the population is logged every cycle.
+    log: Option<BufWriter<File>>,
+    /// The number of cycles actually run per second, measured over roughly
+    /// the last second. This is `None` while paused.
+    achieved_cps: Option<u32>,
+    /// The time spent in each phase of a cycle, if it is being measured.
+    phase_times: Option<PhaseTimes>,
+    /// The population at which to pause the simulation, if any. This is
+    /// cleared once it has been reached.
+    pause_threshold: Option<usize>,
+    /// The total number of cycles at which to pause the simulation, if any.
+    /// This is cleared once it has been reached.
+    stop_cycle: Option<u64>,
+    /// Events from the terminal while the UI is running, used both for
+    /// handling keys and for checking whether long commands should stop.
+    input: Option<InputEvents>,
     /// Whether execution is paused.
     paused: bool,
     /// Whether the app should quit next frame.
     quit: bool,
+    /// Whether the app should quit immediately, without running any further
+    /// commands or handling any further input.
+    force_quit: bool,
+}
+
+/// Derive the cosmic ray, grid, and organism-killing RNGs from a seed.
+fn rngs_from_seed(seed: u64) -> (StdRng, StdRng, StdRng) {
+    let mut rng  = StdRng::seed_from_u64(seed);
+    let grid_rng = StdRng::seed_from_u64(rng.gen());
+    let kill_rng = StdRng::seed_from_u64(rng.gen());
+    (rng, grid_rng, kill_rng)
 }
 
 // Utility methods.
 impl<W: Write> AppState<W> {
     /// Create an organism and add it to the list.
     fn spawn_organism(&mut self) {
-        let pos = self.absolute(self.ui.selection().unwrap_or(ORIGIN));
+        let pos = self.selection_start();
         self.organisms.insert(OrganismState::init(pos));
     }
-    /// Turn a point relative to the view into a point relative to the grid.
-    fn absolute(&self, p: Point) -> Point {
-        let offset = self.ui.view_offset;
-        let x = (offset.x + p.x) % self.grid.width();
-        let y = (offset.y + p.y) % self.grid.height();
-        Point { x, y }
+    /// Get the selected point, or the top-left corner of the view if
+    /// nothing is selected.
+    fn selection_start(&self) -> Point {
+        self.ui.selection().unwrap_or(self.ui.view_offset)
+    }
+    /// Get the points of a rectangle whose top-left corner is the selection
+    /// (or the top-left corner of the view if nothing is selected).
+    fn selected_region(&self, width: usize, height: usize) -> Vec<Point> {
+        let start = self.selection_start();
+        let width = std::cmp::min(width, self.grid.width());
+        let height = std::cmp::min(height, self.grid.height());
+        self.grid.view(start, width, height)
+            .flatten()
+            .map(|(p, _)| p)
+            .collect()
     }
     /// Get the value of the byte that is currently selected.
     fn get_selected_byte(&self) -> Option<u8> {
         self.ui.selection()
-            .map(|p| self.grid[self.absolute(p)])
+            .map(|p| self.grid[p])
     }
-    /// Repeatedly make random modifications to the grid.
-    fn cosmic_rays(&mut self) {
-        for _ in 0..self.config.cosmic_ray_rate {
-            let x = self.cosmic_ray_rng.gen_range(0, self.grid.width());
-            let y = self.cosmic_ray_rng.gen_range(0, self.grid.height());
-            let val = self.cosmic_ray_rng.gen();
-            self.grid.set(Point { x, y }, val);
+    /// Copy the current simulation state.
+    fn take_snapshot(&self) -> Snapshot {
+        Snapshot {
+            total_cycles: self.total_cycles,
+            cycles_since_dedup: self.cycles_since_dedup,
+            cosmic_ray_rng: self.cosmic_ray_rng.clone(),
+            organisms: self.organisms.clone(),
+            grid: self.grid.clone(),
+            focus: self.focus,
+        }
+    }
+    /// Replace the current simulation state with a snapshot. Settings stored
+    /// on the grid and the organism collection are left as they are.
+    fn restore_snapshot(&mut self, snapshot: Snapshot) {
+        let Snapshot { mut organisms, mut grid, .. } = snapshot;
+        organisms.max = self.organisms.max;
+        organisms.max_children = self.organisms.max_children;
+        organisms.max_age = self.organisms.max_age;
+        organisms.kill_policy = self.organisms.kill_policy;
+        organisms.fuel = self.organisms.fuel;
+        organisms.energy = self.organisms.energy;
+        grid.write_error_chance = self.grid.write_error_chance;
+        grid.wall_pierce_chance = self.grid.wall_pierce_chance;
+        grid.wrap = self.grid.wrap;
+        grid.diagonals = self.grid.diagonals;
+        self.total_cycles = snapshot.total_cycles;
+        self.cycles_since_dedup = snapshot.cycles_since_dedup;
+        self.cosmic_ray_rng = snapshot.cosmic_ray_rng;
+        self.organisms = organisms;
+        self.grid = grid;
+        self.focus = snapshot.focus;
+    }
+    /// Warn if the view window doesn't fit in the terminal.
+    fn warn_if_view_too_big(&mut self) {
+        let (term_width, term_height) = match termion::terminal_size() {
+            Ok(size) if self.ui.has_stdout() => size,
+            _ => return,
+        };
+        let (view_width, view_height) = self.ui.view_size();
+        // Each cell takes 3 columns plus a gutter on each side, and the
+        // command line and info box are below the view window.
+        let mut warnings = Vec::new();
+        if view_width as u32 * 3 + 3 > term_width as u32 {
+            warnings.push(format!(
+                "Warning: the view window is {} cells wide, but only {} fit in the terminal.",
+                view_width, term_width.saturating_sub(3) / 3));
+        }
+        if view_height as u32 + 5 > term_height as u32 {
+            warnings.push(format!(
+                "Warning: the view window is {} cells tall, but only {} fit in the terminal.",
+                view_height, term_height.saturating_sub(5)));
         }
+        if !warnings.is_empty() {
+            self.ui.info(warnings);
+        }
+    }
+    /// Write a value to a cell on behalf of a command, recording the previous
+    /// value so that it can be undone.
+    fn edit(&mut self, p: Point, val: u8) {
+        if let Some(edits) = &mut self.pending_edits {
+            edits.push((p, self.grid[p]));
+        }
+        self.grid.set(p, val);
+    }
+    /// Restore the previous values recorded by a group of edits, and return
+    /// a group of edits that would reverse the restoration.
+    fn revert_edits(&mut self, edits: Vec<(Point, u8)>) -> Vec<(Point, u8)> {
+        // Restore in reverse so that cells edited more than once end up with
+        // their original value.
+        edits.into_iter().rev().map(|(p, old)| {
+            let current = self.grid[p];
+            self.grid.set_exact(p, old);
+            (p, current)
+        }).collect()
+    }
+    /// Revert the edits made by the last command that edited the grid.
+    /// Return the number of cells restored, or `None` if there was nothing
+    /// to undo.
+    fn undo_edits(&mut self) -> Option<usize> {
+        let edits = self.edit_history.pop_back()?;
+        let n = edits.len();
+        let inverse = self.revert_edits(edits);
+        self.redo_history.push(inverse);
+        Some(n)
+    }
+    /// Reapply the edits most recently reverted by `undo_edits`. Return the
+    /// number of cells changed, or `None` if there was nothing to redo.
+    fn redo_edits(&mut self) -> Option<usize> {
+        let edits = self.redo_history.pop()?;
+        let n = edits.len();
+        let inverse = self.revert_edits(edits);
+        if self.edit_history.len() == EDIT_HISTORY_LEN {
+            self.edit_history.pop_front();
+        }
+        self.edit_history.push_back(inverse);
+        Some(n)
+    }
+    /// Write a random value to a cell. Return whether the cell's value changed.
+    fn cosmic_ray_at(&mut self, p: Point) -> bool {
+        let old = self.grid[p];
+        let val = self.cosmic_ray_rng.gen();
+        self.grid.set(p, val);
+        self.grid[p] != old
+    }
+    /// Write a random value to a random cell. Return whether the cell's
+    /// value changed.
+    fn cosmic_ray(&mut self) -> bool {
+        let x = self.cosmic_ray_rng.gen_range(0, self.grid.width());
+        let y = self.cosmic_ray_rng.gen_range(0, self.grid.height());
+        self.cosmic_ray_at(Point { x, y })
+    }
+    /// Make the random modifications to the grid that happen every cycle.
+    /// Unlike `cosmic_ray`, these aren't subject to write errors.
+    fn cosmic_rays(&mut self) {
+        self.grid.apply_random(self.config.cosmic_ray_rate, &mut self.cosmic_ray_rng);
+    }
+    /// Replace every RNG with ones derived from a new seed.
+    fn reseed(&mut self, seed: u64) {
+        let (rng, grid_rng, kill_rng) = rngs_from_seed(seed);
+        self.cosmic_ray_rng = rng;
+        self.grid.set_rng(grid_rng);
+        self.organisms.set_kill_rng(kill_rng);
+        self.config.rng_seed = seed;
     }
 }
 
 // The main simulation loop.
 impl<W: Write> AppState<W> {
-    /// Perform a cycle for all organisms.
-    fn cycle(&mut self) {
-        self.organisms.run_cycle(&mut self.grid, self.organisms.max);
-        self.cosmic_rays();
+    /// Append the current cycle count and population to the log, if any.
+    /// Stop logging if this fails.
+    fn write_log(&mut self) {
+        if let Some(log) = &mut self.log {
+            let mut result = writeln!(log, "{},{}", self.total_cycles, self.organisms.len());
+            if result.is_ok() && self.total_cycles.is_multiple_of(LOG_FLUSH_INTERVAL) {
+                result = log.flush();
+            }
+            if result.is_err() {
+                self.log = None;
+                self.ui.info1("Couldn't write to the log file. Logging has stopped.");
+            }
+        }
+    }
+    /// Run `f`, adding the time it takes to one of the phases in
+    /// `phase_times` if they are being measured.
+    fn timed<T>(
+        &mut self,
+        phase: fn(&mut PhaseTimes) -> &mut Duration,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        if self.phase_times.is_none() {
+            return f(self);
+        }
+        let start = Instant::now();
+        let result = f(self);
+        if let Some(times) = &mut self.phase_times {
+            *phase(times) += start.elapsed();
+        }
+        result
+    }
+    /// Perform a cycle for all organisms. If a trigger such as `stop-at`
+    /// paused the simulation, return the alert that was shown.
+    fn cycle(&mut self) -> Option<String> {
+        let was_populated = self.organisms.len() != 0;
+        let clock = self.total_cycles as u8;
+        self.timed(|t| &mut t.organisms, |app| app.organisms.run_cycle(&mut app.grid, clock));
+        self.timed(|t| &mut t.cosmic_rays, Self::cosmic_rays);
         // If the focused organism is no longer alive, set it to `None`.
         if let Some(id) = self.focus {
             if !self.organisms.alive(id) {
                 self.focus = None;
             }
         }
+        // Stop watching organisms that are no longer alive.
+        let organisms = &self.organisms;
+        self.watch.retain(|&id| organisms.alive(id));
         self.total_cycles += 1;
         self.cycles_since_dedup += 1;
         let rate = self.config.dedup_rate;
         if rate != 0 && self.cycles_since_dedup >= rate {
             self.cycles_since_dedup = 0;
-            self.organisms.dedup();
+            self.timed(|t| &mut t.dedup, |app| app.organisms.dedup());
+        }
+        self.write_log();
+        let mut alert = None;
+        if self.config.pause_on_extinction && was_populated && self.organisms.len() == 0 {
+            alert = Some("All organisms have died. Paused.".to_string());
         }
+        if let Some(threshold) = self.pause_threshold {
+            if self.organisms.len() >= threshold {
+                self.pause_threshold = None;
+                alert = Some(format!(
+                    "The population has reached {}. Paused.", self.organisms.len()));
+            }
+        }
+        if let Some(stop_cycle) = self.stop_cycle {
+            if self.total_cycles >= stop_cycle {
+                self.stop_cycle = None;
+                alert = Some(format!("Reached cycle {}. Paused.", self.total_cycles));
+            }
+        }
+        if let Some(alert) = &alert {
+            self.paused = true;
+            self.ui.info1(alert.clone());
+        }
+        alert
     }
 }
 
@@ -211,11 +574,25 @@ impl<W: Write> AppState<W> {
         if options.grid_height == 0 {
             return Err(Error::BadHeight);
         }
+        if options.view_width == 0 {
+            return Err(Error::BadViewWidth);
+        }
+        if options.view_height == 0 {
+            return Err(Error::BadViewHeight);
+        }
+        let fill = match options.fill.as_str() {
+            "random" => None,
+            s => match Instruction::from_symbol(s) {
+                Some(ins) => Some(ins as u8),
+                None => return Err(Error::UnknownFill(options.fill)),
+            },
+        };
+        // A view window larger than the grid would show some cells twice.
+        let view_width = std::cmp::min(options.view_width as usize, options.grid_width) as u16;
+        let view_height = std::cmp::min(options.view_height as usize, options.grid_height) as u16;
         // Initialize the RNGs.
         let rng_seed = options.rng_seed.unwrap_or_else(rand::random);
-        let mut rng  = StdRng::seed_from_u64(rng_seed);
-        let grid_rng = StdRng::seed_from_u64(rng.gen());
-        let kill_rng = StdRng::seed_from_u64(rng.gen());
+        let (rng, grid_rng, kill_rng) = rngs_from_seed(rng_seed);
         // Create the app.
         let mut app = Self {
             total_cycles: 0,
@@ -226,17 +603,34 @@ impl<W: Write> AppState<W> {
                 options.grid_width,
                 options.grid_height,
                 grid_rng,
-                Instruction::Nop as u8,
+                fill,
                 options.write_error_chance,
             ),
             config: Config::new(rng_seed),
             commands: Commands::new(),
-            ui: UI::new(stdout, options.view_width, options.view_height),
+            command_depth: 0,
+            ui: UI::new(stdout, view_width, view_height, !options.no_color),
             focus: None,
+            watch: Vec::new(),
+            snapshot: None,
+            step_history: VecDeque::new(),
+            edit_history: VecDeque::new(),
+            redo_history: Vec::new(),
+            pending_edits: None,
+            log: None,
+            achieved_cps: None,
+            phase_times: None,
+            pause_threshold: None,
+            stop_cycle: None,
+            input: None,
             paused: false,
             quit: false,
+            force_quit: false,
         };
+        app.grid.wrap = !options.bounded;
+        app.grid.diagonals = options.diagonals;
         app.ui.clear();
+        app.warn_if_view_too_big();
         // Run commands in an initialization file if one was passed.
         if let Some(f) = options.initial_file {
             app.run_commands_in_file(&f);
@@ -246,72 +640,174 @@ impl<W: Write> AppState<W> {
     fn run_commands_in_file(&mut self, path: impl AsRef<std::path::Path>) {
         if let Ok(contents) = std::fs::read_to_string(&path) {
             for command in contents.lines() {
-                self.run_command(command);
+                self.report_command(command);
             }
         } else {
             self.ui.info1(format!("Cannot read file '{}'.", path.as_ref().display()));
         }
     }
-    fn run_command(&mut self, command: &str) {
+    /// Run a command, displaying the error if it fails.
+    fn report_command(&mut self, command: &str) {
+        if let Err(e) = self.run_command(command) {
+            self.ui.info1(e.description());
+        }
+    }
+    fn run_command(&mut self, command: &str) -> Result<(), command::Error> {
+        if self.force_quit {
+            return Ok(());
+        }
         let command = command.trim();
         // Do nothing if it's a comment
-        if command.as_bytes().get(0) == Some(&b'#') {
-            return;
+        if command.as_bytes().first() == Some(&b'#') {
+            return Ok(());
         }
         let mut args = Args::from_command(command);
-        match args.next_raw() {
-            None => {}
-            Some(head) => {
-                let handler = self.commands.handlers.get(head);
-                if let Some(handler) = handler {
-                    match Rc::clone(handler).run(self, args) {
-                        Ok(()) => self.commands.last = Some(command.to_string()),
-                        Err(e) => self.ui.info1(e.description()),
-                    }
-                } else {
-                    self.ui.info1(format!("Command '{}' does not exist.", head))
+        let head = match args.next_raw() {
+            None => return Ok(()),
+            Some(head) => head,
+        };
+        let handler = match self.commands.handlers.get(head) {
+            Some(handler) => Rc::clone(handler),
+            None => return Err(command::Error::UnknownCommand(head.to_string())),
+        };
+        // Edits made by nested commands are grouped with those of the
+        // outermost command.
+        let outermost = self.pending_edits.is_none();
+        if outermost {
+            self.pending_edits = Some(Vec::new());
+        }
+        let result = handler.run(self, args);
+        if result.is_ok() {
+            self.commands.last = Some(command.to_string());
+        }
+        if outermost {
+            let edits = self.pending_edits.take().unwrap_or_default();
+            if !edits.is_empty() {
+                self.redo_history.clear();
+                if self.edit_history.len() == EDIT_HISTORY_LEN {
+                    self.edit_history.pop_front();
                 }
+                self.edit_history.push_back(edits);
             }
         }
+        result
+    }
+    /// Run a command from within another command.
+    fn run_nested_command(&mut self, command: &str) -> Result<(), command::Error> {
+        if self.command_depth >= MAX_COMMAND_DEPTH {
+            return Err(command::Error::TooDeep);
+        }
+        self.command_depth += 1;
+        let result = self.run_command(command);
+        self.command_depth -= 1;
+        result
     }
-    fn handle_key<R: Read>(&mut self, key: Key, key_input: &mut termion::input::Keys<R>) {
+    fn handle_key(&mut self, key: Key) {
         let grid_width = self.grid.width();
         let grid_height = self.grid.height();
         match key {
-            Key::Char(':') => if let Some(cmd) = self.ui.input_command(key_input) {
-                self.run_command(&cmd);
+            Key::Char(':') => if let Some(events) = &mut self.input {
+                let names: Vec<&str> = self.commands.handlers.keys()
+                    .map(String::as_str)
+                    .collect();
+                if let Some(cmd) = self.ui.input_command(events.as_mut(), &names) {
+                    self.report_command(&cmd);
+                }
             }
             Key::Char('.') => if let Some(cmd) = &self.commands.last {
                 let cmd = cmd.clone();
-                self.run_command(&cmd);
+                self.report_command(&cmd);
             }
-            Key::Char(' ') => if self.paused { self.cycle() }
+            Key::Char(' ') if self.paused => self.step(),
+            Key::Char('b') => self.step_back(),
             Key::Char('h') => self.ui.move_view_offset(Dir::L, grid_width, grid_height),
             Key::Char('j') => self.ui.move_view_offset(Dir::D, grid_width, grid_height),
             Key::Char('k') => self.ui.move_view_offset(Dir::U, grid_width, grid_height),
             Key::Char('l') => self.ui.move_view_offset(Dir::R, grid_width, grid_height),
             Key::Char('w') => self.ui.info_scroll_up(),
             Key::Char('s') => self.ui.info_scroll_down(),
-            Key::Right => self.ui.move_selection(Dir::R),
-            Key::Left  => self.ui.move_selection(Dir::L),
-            Key::Down  => self.ui.move_selection(Dir::D),
-            Key::Up    => self.ui.move_selection(Dir::U),
+            Key::Char('W') => self.ui.status_scroll_up(),
+            Key::Char('S') => self.ui.status_scroll_down(),
+            Key::Right => self.ui.move_selection_n(Dir::R, 1, grid_width, grid_height),
+            Key::Left  => self.ui.move_selection_n(Dir::L, 1, grid_width, grid_height),
+            Key::Down  => self.ui.move_selection_n(Dir::D, 1, grid_width, grid_height),
+            Key::Up    => self.ui.move_selection_n(Dir::U, 1, grid_width, grid_height),
             Key::Char('p') => self.toggle_pause(),
             Key::Esc => self.ui.select(None),
             _ => {}
         }
     }
-    fn check_inputs<R: Read>(&mut self, key_input: &mut termion::input::Keys<R>) {
-        // Read key presses since the last update.
-        while let Some(key) = key_input.next() {
-            self.handle_key(key.unwrap(), key_input);
+    /// Return the next event from the terminal, if there is one.
+    fn next_event(&mut self) -> Option<Event> {
+        self.input.as_mut()?.next().map(Result::unwrap)
+    }
+    /// Run up to `n` cycles, stopping early if Esc is pressed or a trigger
+    /// pauses the simulation. Return the number of cycles that were run,
+    /// along with the trigger's alert if there was one.
+    fn run_cycles(&mut self, n: u32) -> (u32, Option<String>) {
+        for i in 0..n {
+            if i != 0 && i % ABORT_CHECK_INTERVAL == 0 && self.abort_requested() {
+                return (i, None);
+            }
+            if let Some(alert) = self.cycle() {
+                return (i + 1, Some(alert));
+            }
+        }
+        (n, None)
+    }
+    /// Determine whether Esc has been pressed since the last check, so that
+    /// a long-running command can stop early. Other input is discarded.
+    fn abort_requested(&mut self) -> bool {
+        let mut abort = false;
+        while let Some(event) = self.next_event() {
+            if let Event::Key(Key::Esc) = event {
+                abort = true;
+            }
+        }
+        abort
+    }
+    fn check_inputs(&mut self) {
+        use termion::event::{MouseButton, MouseEvent};
+        // Read key presses and mouse clicks since the last update.
+        while let Some(event) = self.next_event() {
+            match event {
+                Event::Key(key) => self.handle_key(key),
+                Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)) => {
+                    self.ui.click(x, y, self.grid.width(), self.grid.height());
+                }
+                _ => {}
+            }
             if self.quit {
                 break;
             }
         }
     }
+    /// Run a single cycle while paused, recording the state beforehand so
+    /// that it can be undone.
+    fn step(&mut self) {
+        let cells = self.grid.width() * self.grid.height();
+        let max_len = (STEP_HISTORY_CELLS / cells).clamp(1, STEP_HISTORY_LEN);
+        while self.step_history.len() >= max_len {
+            self.step_history.pop_front();
+        }
+        self.step_history.push_back(self.take_snapshot());
+        self.cycle();
+    }
+    /// Undo the last cycle run by `step`.
+    fn step_back(&mut self) {
+        if let Some(snapshot) = self.step_history.pop_back() {
+            self.restore_snapshot(snapshot);
+        } else {
+            self.ui.info1("Nothing to undo.");
+        }
+    }
     fn toggle_pause(&mut self) {
         self.paused = !self.paused;
+        if !self.paused {
+            // Stepping back after the simulation has run freely would be
+            // confusing, so forget the history.
+            self.step_history.clear();
+        }
         self.ui.info1(
             if self.paused {
                 "Paused."
@@ -323,32 +819,301 @@ impl<W: Write> AppState<W> {
     pub fn num_organisms(&self) -> usize {
         self.organisms.len()
     }
-    pub fn run<R: Read>(&mut self, mut key_input: termion::input::Keys<R>) {
-        use std::time::Duration;
-        let frame_frequency_ms = 16u64;
-        let frame_frequency = Duration::from_millis(frame_frequency_ms);
+    /// Draw the grid and everything around it.
+    fn render(&mut self) {
+        let focused = self.organisms.get_opt(self.focus).map(|ctx| &ctx.organism);
+        let occupied = self.organisms.iter().map(|ctx| ctx.organism.ip).collect();
+        self.ui.render_grid(&self.grid, focused, occupied);
+        self.ui.render_ruler(self.grid.width(), self.grid.height());
+        let organisms = &self.organisms;
+        let watched = self.watch.iter()
+            .filter_map(|&id| organisms.get(id))
+            .collect();
+        // Only show the achieved rate if it falls noticeably short of the target.
+        let lagging_cps = match (self.config.cycles_per_second, self.achieved_cps) {
+            (Some(target), Some(achieved)) if achieved < target / 10 * 9 => Some(achieved),
+            _ => None,
+        };
+        let status = Status {
+            total_cycles: self.total_cycles,
+            paused: self.paused,
+            num_organisms: self.organisms.len(),
+            lagging_cps,
+            selected_byte: self.get_selected_byte(),
+        };
+        self.ui.render_status_box(
+            status,
+            self.organisms.get_opt(self.focus),
+            watched,
+        );
+        // The minimap is drawn after the status box because clearing the
+        // status box clears the rest of each line.
+        self.ui.render_minimap(&self.grid);
+        self.ui.flush();
+    }
+    pub fn run<R: Read + 'static>(&mut self, events: termion::input::Events<R>) {
+        self.input = Some(Box::new(events));
         let mut time_since_last_cycle = 0;
+        // The number of cycles owed when targeting a number of cycles per
+        // second, in thousandths of a cycle.
+        let mut cycle_credit = 0;
+        // Used to measure the number of cycles actually run per second.
+        let mut rate_window_start = Instant::now();
+        let mut rate_window_cycles = 0;
         while !self.quit {
+            // This is read every frame since it can be changed by `fps`.
+            let frame_frequency_ms = self.config.frame_frequency_ms as u64;
             if !self.paused {
-                time_since_last_cycle += frame_frequency_ms;
-                let cycle_frequency = self.config.cycle_frequency as u64;
-                while time_since_last_cycle > cycle_frequency {
-                    self.cycle();
-                    time_since_last_cycle -= cycle_frequency;
+                let cycles_before = self.total_cycles;
+                if let Some(cps) = self.config.cycles_per_second {
+                    // Only a fraction of a cycle is carried over from earlier
+                    // frames, so that cycles owed from before a pause or a
+                    // rate change don't all run at once.
+                    cycle_credit = std::cmp::min(cycle_credit, 999) + cps as u64 * frame_frequency_ms;
+                    while !self.paused && cycle_credit >= 1000 {
+                        self.cycle();
+                        cycle_credit -= 1000;
+                    }
+                } else {
+                    time_since_last_cycle += frame_frequency_ms;
+                    let cycle_frequency = self.config.cycle_frequency as u64;
+                    while !self.paused && time_since_last_cycle > cycle_frequency {
+                        self.cycle();
+                        time_since_last_cycle -= cycle_frequency;
+                    }
+                }
+                rate_window_cycles += self.total_cycles - cycles_before;
+                let elapsed = rate_window_start.elapsed();
+                if elapsed >= Duration::from_secs(1) {
+                    let rate = rate_window_cycles as f64 / elapsed.as_secs_f64();
+                    self.achieved_cps = Some(rate as u32);
+                    rate_window_start = Instant::now();
+                    rate_window_cycles = 0;
                 }
+            } else {
+                self.achieved_cps = None;
+                rate_window_start = Instant::now();
+                rate_window_cycles = 0;
+            }
+            self.render();
+            self.check_inputs();
+            if self.force_quit {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(frame_frequency_ms));
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use structopt::StructOpt as _;
+
+    use super::*;
+
+    /// Create a headless app with a seeded grid without write errors, given
+    /// extra options.
+    pub(super) fn app(width: usize, height: usize, args: &[&str]) -> AppState<Vec<u8>> {
+        let (width, height) = (width.to_string(), height.to_string());
+        let mut full = vec![
+            "myco", "--width", &width, "--height", &height, "--seed", "0",
+            "--write-error-chance", "0",
+        ];
+        full.extend_from_slice(args);
+        AppState::init(Options::from_iter(&full), None).ok().unwrap()
+    }
+
+    /// Return a path in the temporary directory that doesn't exist yet.
+    pub(super) fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("myco-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn nested_errors_propagate() {
+        let mut app = app(20, 20, &[]);
+        let result = app.run_command("repeat 3 if-empty nonexistent");
+        assert!(matches!(result, Err(command::Error::UnknownCommand(ref s)) if s == "nonexistent"));
+        assert!(app.run_command("if-alive nonexistent").is_ok());
+    }
+
+    #[test]
+    fn step_history_is_bounded_by_grid_size() {
+        let mut small = app(20, 20, &[]);
+        for _ in 0..STEP_HISTORY_LEN + 1 {
+            small.step();
+        }
+        assert_eq!(small.step_history.len(), STEP_HISTORY_LEN);
+
+        let mut large = app(2048, 2048, &[]);
+        for _ in 0..STEP_HISTORY_LEN {
+            large.step();
+        }
+        assert_eq!(large.step_history.len(), STEP_HISTORY_CELLS / (2048 * 2048));
+    }
+
+    #[test]
+    fn clock_changes_between_cycles() {
+        let mut app = app(20, 20, &["--fill", "at"]);
+        app.spawn_organism();
+        let mut values = Vec::new();
+        for _ in 0..3 {
+            app.cycle();
+            values.push(app.organisms.iter().next().unwrap().organism.ax);
+        }
+        assert_eq!(values, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn seeded_runs_are_deterministic() {
+        let run = || {
+            let mut app = app(60, 60, &[]);
+            app.run_commands_in_file("patterns/dense_replicator.myco");
+            for _ in 0..500 {
+                app.cycle();
             }
-            let focused = self.organisms.get_opt(self.focus).map(|ctx| &ctx.organism);
-            let occupied = self.organisms.iter().map(|ctx| ctx.organism.ip).collect();
-            self.ui.render_grid(&self.grid, focused, occupied);
-            self.ui.render_status_box(
-                self.total_cycles,
-                self.organisms.len(),
-                self.get_selected_byte(),
-                focused,
-            );
-            self.ui.flush();
-            self.check_inputs(&mut key_input);
-            std::thread::sleep(frame_frequency);
-        }
-    }
-}
\ No newline at end of file
+            assert!(app.organisms.len() > 1);
+            let rows: Vec<_> = (0..60).map(|y| app.grid.row_slice(y, 0, 60)).collect();
+            let organisms: Vec<_> = app.organisms.iter().map(|c| c.organism.clone()).collect();
+            (rows, organisms)
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn triggers_stop_batches() {
+        let mut app = app(20, 20, &[]);
+        assert!(app.run_command("stop-at 5").is_ok());
+        assert!(app.run_command("cycle 100").is_ok());
+        assert_eq!(app.total_cycles, 5);
+        assert!(app.paused);
+
+        assert!(app.run_command("stop-at 10").is_ok());
+        assert!(app.run_command("cycle 100 render 3").is_ok());
+        assert_eq!(app.total_cycles, 10);
+
+        assert!(app.run_command("stop-at 20").is_ok());
+        assert!(app.run_command("bench 100").is_ok());
+        assert_eq!(app.total_cycles, 20);
+
+        assert!(app.run_command("stop-at 30").is_ok());
+        assert!(app.run_command("profile-phases 100").is_ok());
+        assert_eq!(app.total_cycles, 30);
+
+        let heatmap = temp_path("trigger-heatmap.png");
+        assert!(app.run_command("stop-at 40").is_ok());
+        assert!(app.run_command(&format!("export-heatmap {} 100", heatmap.display())).is_ok());
+        assert_eq!(app.total_cycles, 40);
+        assert!(heatmap.exists());
+        let _ = std::fs::remove_file(&heatmap);
+
+        let gif = temp_path("trigger.gif");
+        assert!(app.run_command("stop-at 50").is_ok());
+        assert!(app.run_command(&format!("export-gif {} 100 3", gif.display())).is_ok());
+        assert_eq!(app.total_cycles, 50);
+        assert!(gif.exists());
+        let _ = std::fs::remove_file(&gif);
+
+        app.grid.set(app.selection_start(), Instruction::Halt as u8);
+        app.spawn_organism();
+        assert!(app.run_command("pause-on-extinction").is_ok());
+        assert!(app.run_command("cycle 100").is_ok());
+        assert_eq!(app.organisms.len(), 0);
+        assert_eq!(app.total_cycles, 51);
+    }
+
+    fn grid_rows<W: Write>(app: &AppState<W>) -> Vec<Vec<u8>> {
+        let (width, height) = (app.grid.width(), app.grid.height());
+        (0..height).map(|y| app.grid.row_slice(y, 0, width)).collect()
+    }
+
+    #[test]
+    fn undo_clear() {
+        let mut app = app(20, 20, &["--fill", "random"]);
+        let before = grid_rows(&app);
+        assert!(app.run_command("clear 5 4").is_ok());
+        assert_ne!(grid_rows(&app), before);
+        assert!(app.run_command("undo").is_ok());
+        assert_eq!(grid_rows(&app), before);
+
+        assert!(app.run_command("clear").is_ok());
+        assert!(app.run_command("undo").is_ok());
+        assert_eq!(grid_rows(&app), before);
+    }
+
+    #[test]
+    fn redo_reproduces_edits() {
+        let mut app = app(20, 20, &["--fill", "random"]);
+        app.ui.select(Some(Point { x: 17, y: 17 }));
+        let before = grid_rows(&app);
+        assert!(app.run_command("soup 6 6").is_ok());
+        let after = grid_rows(&app);
+        assert_ne!(after, before);
+        assert!(app.run_command("undo").is_ok());
+        assert_eq!(grid_rows(&app), before);
+        assert!(app.run_command("redo").is_ok());
+        assert_eq!(grid_rows(&app), after);
+        assert!(app.run_command("undo").is_ok());
+        assert!(app.run_command("redo").is_ok());
+        assert_eq!(grid_rows(&app), after);
+    }
+
+    #[test]
+    fn view_is_limited_to_small_grids() {
+        let mut app = app(3, 3, &[]);
+        assert_eq!(app.ui.view_size(), (3, 3));
+        assert_eq!(app.ui.set_view_size(10, 10, 3, 3), (3, 3));
+        app.ui.move_view_offset(Dir::R, 3, 3);
+        app.ui.move_view_offset(Dir::D, 3, 3);
+        let offset = app.ui.view_offset;
+        let mut seen = std::collections::HashSet::new();
+        for (y, row) in app.grid.view(offset, 3, 3).enumerate() {
+            for (x, (p, _)) in row.enumerate() {
+                assert!(seen.insert(p));
+                assert_eq!(p.in_view(offset, 3, 3, 3, 3), Some(Point { x, y }));
+            }
+        }
+        assert_eq!(seen.len(), 9);
+    }
+
+    /// Check that the highlighted cell is the selected one.
+    fn assert_highlight_matches<W: Write>(app: &AppState<W>) {
+        let (width, height) = app.ui.view_size();
+        let mark = app.ui.selection_mark().unwrap();
+        let (p, byte) = app.grid.view(app.ui.view_offset, width as usize, height as usize)
+            .nth(mark.y).unwrap()
+            .nth(mark.x).unwrap();
+        assert_eq!(Some(p), app.ui.selection());
+        assert_eq!(Some(byte), app.get_selected_byte());
+    }
+
+    #[test]
+    fn selection_follows_scrolling() {
+        let mut app = app(20, 20, &["--fill", "random", "--view-width", "5", "--view-height", "5"]);
+        // Click the cell in the third column and second row of the view.
+        app.ui.click(2 + 3 * 2, 2 + 1, 20, 20);
+        app.render();
+        assert_eq!(app.ui.selection_mark(), Some(Point { x: 2, y: 1 }));
+        assert_highlight_matches(&app);
+
+        app.ui.move_view_offset(Dir::R, 20, 20);
+        app.ui.move_view_offset(Dir::U, 20, 20);
+        app.render();
+        assert_eq!(app.ui.selection_mark(), Some(Point { x: 1, y: 2 }));
+        assert_highlight_matches(&app);
+
+        // Moving the selection past the edge of the view scrolls it.
+        app.ui.move_selection_n(Dir::R, 4, 20, 20);
+        app.render();
+        assert_eq!(app.ui.selection(), Some(Point { x: 6, y: 1 }));
+        assert_highlight_matches(&app);
+
+        // The selection isn't highlighted once it's scrolled out of view.
+        for _ in 0..10 {
+            app.ui.move_view_offset(Dir::D, 20, 20);
+        }
+        app.render();
+        assert_eq!(app.ui.selection_mark(), None);
+        assert_eq!(app.ui.selection(), Some(Point { x: 6, y: 1 }));
+    }
+}
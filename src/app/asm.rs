@@ -0,0 +1,162 @@
+//! A simple assembler that lays out programs written in a text format.
+//!
+//! Each non-empty line of the source is a row of the program, and each
+//! whitespace-separated token is either:
+//!
+//! - an instruction symbol, which occupies one cell;
+//! - `#`, which begins a comment lasting until the end of the line;
+//! - `NAME:`, which defines a label referring to the position of the next cell;
+//! - a directive beginning with `.`, described in `doc/commands.md`.
+
+use std::collections::HashMap;
+
+use super::instruction::Instruction;
+
+/// The maximum number of cells in a row of a program.
+const MAX_ROW_LEN: usize = 1 << 16;
+
+/// An error encountered while assembling a program.
+pub struct AsmError {
+    /// The 1-based line number on which the error occurred.
+    pub line: usize,
+    pub message: String,
+}
+
+/// A single cell of the program before labels are resolved.
+enum Item {
+    /// Write a byte.
+    Byte(u8),
+    /// Leave the cell unchanged.
+    Skip,
+    /// Write the column of a label.
+    LabelX(String),
+    /// Write the row of a label.
+    LabelY(String),
+}
+
+/// An assembled program. `None` represents a cell that should be left unchanged.
+pub type Program = Vec<Vec<Option<u8>>>;
+
+/// Determine whether a token defines a label, returning its name if so.
+fn label_name(token: &str) -> Option<&str> {
+    let name = token.strip_suffix(':')?;
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let mut rows = Vec::new();
+    let mut labels = HashMap::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_no = line_idx + 1;
+        let error = |message: String| AsmError { line: line_no, message };
+        let mut row = Vec::new();
+        let mut tokens = line.split_whitespace().take_while(|&t| t != "#");
+        while let Some(token) = tokens.next() {
+            if let Some(name) = label_name(token) {
+                let pos = (row.len(), rows.len());
+                if labels.insert(name.to_string(), pos).is_some() {
+                    return Err(error(format!("Label '{}' is defined twice.", name)));
+                }
+            } else if token.starts_with('.') && token.len() > 2 {
+                // Directives are distinguished from instructions like `.a` by
+                // their length.
+                let arg = tokens.next()
+                    .ok_or_else(|| error(format!("Expected argument to '{}'.", token)))?;
+                match token {
+                    ".byte" => row.push(Item::Byte(arg.parse()
+                        .map_err(|_| error(format!("Invalid byte '{}'.", arg)))?)),
+                    ".skip" => {
+                        let n: usize = arg.parse()
+                            .map_err(|_| error(format!("Invalid number '{}'.", arg)))?;
+                        if n > MAX_ROW_LEN - row.len() {
+                            return Err(error(format!("Row is longer than {} cells.", MAX_ROW_LEN)));
+                        }
+                        row.extend((0..n).map(|_| Item::Skip));
+                    }
+                    ".col" => row.push(Item::LabelX(arg.to_string())),
+                    ".row" => row.push(Item::LabelY(arg.to_string())),
+                    _ => return Err(error(format!("Unknown directive '{}'.", token))),
+                }
+            } else if row.len() == MAX_ROW_LEN {
+                return Err(error(format!("Row is longer than {} cells.", MAX_ROW_LEN)));
+            } else {
+                let ins = Instruction::from_symbol(token)
+                    .ok_or_else(|| error(format!("Unknown instruction '{}'.", token)))?;
+                row.push(Item::Byte(ins as u8));
+            }
+        }
+        if !row.is_empty() {
+            rows.push((line_no, row));
+        }
+    }
+    // Resolve label references now that every label is known.
+    rows.into_iter().map(|(line_no, row)| {
+        row.into_iter().map(|item| {
+            let resolve = |name: &str, coord: fn((usize, usize)) -> usize| {
+                let pos = *labels.get(name).ok_or_else(|| AsmError {
+                    line: line_no,
+                    message: format!("Label '{}' is not defined.", name),
+                })?;
+                let value = coord(pos);
+                if value > u8::MAX as usize {
+                    Err(AsmError {
+                        line: line_no,
+                        message: format!("The position of label '{}' doesn't fit in a byte.", name),
+                    })
+                } else {
+                    Ok(Some(value as u8))
+                }
+            };
+            match item {
+                Item::Byte(b) => Ok(Some(b)),
+                Item::Skip => Ok(None),
+                Item::LabelX(name) => resolve(&name, |(x, _)| x),
+                Item::LabelY(name) => resolve(&name, |(_, y)| y),
+            }
+        }).collect()
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_line(source: &str) -> usize {
+        match assemble(source) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.line,
+        }
+    }
+
+    #[test]
+    fn lays_out_rows() {
+        let program = assemble("# a comment\n.byte 7 .skip 2\n\n.byte 1 # .byte 2\n").ok().unwrap();
+        assert_eq!(program, vec![vec![Some(7), None, None], vec![Some(1)]]);
+    }
+
+    #[test]
+    fn resolves_labels() {
+        let source = ".col end .row end\n.skip 3 end: .byte 0\n";
+        let program = assemble(source).ok().unwrap();
+        assert_eq!(program[0], vec![Some(3), Some(1)]);
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        assert_eq!(error_line(".byte 1\n.byte 256\n"), 2);
+        assert_eq!(error_line("a:\n\na:\n"), 3);
+        assert_eq!(error_line(".col missing\n"), 1);
+        assert_eq!(error_line(".byte 0\n.skip\n"), 2);
+    }
+
+    #[test]
+    fn bounds_skip() {
+        assert_eq!(error_line(".byte 0\n.skip 18446744073709551615\n"), 2);
+        assert_eq!(error_line(&format!(".byte 0 .skip {}\n", MAX_ROW_LEN)), 1);
+        assert!(assemble(&format!(".skip {}\n", MAX_ROW_LEN)).is_ok());
+    }
+}
@@ -16,15 +16,37 @@ pub enum Error {
     BadDirection,
     NoInstruction,
     BadInstruction,
+    UnknownInstruction(String),
     NoNumber,
     BadNumber,
     ZeroSpeed,
+    ZeroFrameRate,
     NoPath,
     WorldTooBig,
+    ImageTooBig,
+    EmptyRegion,
     ZeroGifFrames,
     ZeroStep,
+    ZeroScale,
     ExportFileExists(PathBuf),
     ExportFailure(PathBuf),
+    BadPalette(PathBuf),
+    CannotReadFile(PathBuf),
+    CannotWriteFile(PathBuf),
+    BadAssembly(usize, String),
+    BadListOption(String),
+    BadCycleOption(String),
+    NoSortKey,
+    BadSortKey,
+    NoKillPolicy,
+    BadKillPolicy,
+    NoCategory,
+    BadCategory,
+    NoCommand,
+    UnknownCommand(String),
+    NoSelection,
+    NoFocus,
+    TooDeep,
     Extra(String),
 }
 
@@ -35,17 +57,47 @@ impl Error {
             Error::BadDirection => "Expected one of < > ^ v.".into(),
             Error::NoInstruction  => "Expected instruction.".into(),
             Error::BadInstruction => "Expected instruction.".into(),
+            Error::UnknownInstruction(s) => format!("Unknown instruction '{}'.", s).into(),
             Error::NoNumber  => "Expected number.".into(),
             Error::BadNumber => "Invalid number.".into(),
             Error::ZeroSpeed => "Speed cannot be set to 0.".into(),
+            Error::ZeroFrameRate => "The frame rate cannot be set to 0.".into(),
             Error::NoPath => "Expected filepath.".into(),
             Error::WorldTooBig => "The world is too big to export as a GIF.".into(),
+            Error::ImageTooBig => "The image would be too big to export.".into(),
+            Error::EmptyRegion => "Cannot export an empty region.".into(),
             Error::ZeroGifFrames => "Cannot create GIF with zero frames.".into(),
-            Error::ZeroStep => "Cannot create GIF with zero cycles between frames.".into(),
+            Error::ZeroStep => "The number of cycles between frames cannot be zero.".into(),
+            Error::ZeroScale => "Cannot export with a pixel scale of zero.".into(),
             Error::ExportFileExists(p) =>
                 format!("The file '{}' already exists.", p.display()).into(),
             Error::ExportFailure(p) =>
                 format!("Couldn't export to file '{}'.", p.display()).into(),
+            Error::BadPalette(p) => format!(
+                "Couldn't read a palette from '{}'. Using the default palette.",
+                p.display()).into(),
+            Error::CannotReadFile(p) =>
+                format!("Cannot read file '{}'.", p.display()).into(),
+            Error::CannotWriteFile(p) =>
+                format!("Cannot write to file '{}'.", p.display()).into(),
+            Error::BadAssembly(line, message) => format!("Line {}: {}", line, message).into(),
+            Error::BadListOption(s) =>
+                format!("Unknown option '{}'. Expected 'dir' or 'sort'.", s).into(),
+            Error::BadCycleOption(s) =>
+                format!("Unknown option '{}'. Expected 'render'.", s).into(),
+            Error::NoSortKey  => "Expected one of ax, bx, ip.".into(),
+            Error::BadSortKey => "Expected one of ax, bx, ip.".into(),
+            Error::NoKillPolicy  => "Expected one of random, oldest, youngest, most-children.".into(),
+            Error::BadKillPolicy => "Expected one of random, oldest, youngest, most-children.".into(),
+            Error::NoCategory  =>
+                "Expected one of special, wall, calculation, control, cursor, selection, memory.".into(),
+            Error::BadCategory =>
+                "Expected one of special, wall, calculation, control, cursor, selection, memory.".into(),
+            Error::NoCommand => "Expected command.".into(),
+            Error::UnknownCommand(s) => format!("Command '{}' does not exist.", s).into(),
+            Error::NoSelection => "Nothing is selected.".into(),
+            Error::NoFocus => "No organism is focused.".into(),
+            Error::TooDeep => "Commands are nested too deeply.".into(),
             Error::Extra(s) => format!("Unexpected argument '{}'.", s).into(),
         }
     }
@@ -53,17 +105,33 @@ impl Error {
 
 /// Tracks the command's arguments and its position within them.
 pub struct Args<'a> {
-    args: Vec<&'a str>,
+    /// Each argument, along with the quote character it was surrounded by,
+    /// if any.
+    args: Vec<(&'a str, Option<char>)>,
     pos: usize,
 }
 
 impl<'a> Args<'a> {
-    /// Create the argument list from the original command by splitting on whitespace.
+    /// Create the argument list from the original command by splitting on
+    /// whitespace. An argument starting with `"` or `'` continues until the
+    /// matching quote (or the end of the command), so it can contain spaces.
     pub fn from_command(c: &'a str) -> Self {
-        Self {
-            args: c.split_whitespace().collect(),
-            pos: 0,
+        let mut args = Vec::new();
+        let mut rest = c.trim_start();
+        while let Some(first) = rest.chars().next() {
+            if first == '"' || first == '\'' {
+                let quoted = &rest[1..];
+                let end = quoted.find(first).unwrap_or(quoted.len());
+                args.push((&quoted[..end], Some(first)));
+                rest = quoted.get(end + 1..).unwrap_or("");
+            } else {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                args.push((&rest[..end], None));
+                rest = &rest[end..];
+            }
+            rest = rest.trim_start();
         }
+        Self { args, pos: 0 }
     }
     /// Return the next argument as a string.
     pub fn next_raw(&mut self) -> Option<&str> {
@@ -71,7 +139,20 @@ impl<'a> Args<'a> {
         if result.is_some() {
             self.pos += 1;
         }
-        result.copied()
+        result.map(|&(arg, _)| arg)
+    }
+    /// Return all remaining arguments joined by spaces. Quoted arguments are
+    /// quoted again so that the result can be parsed as a command.
+    pub fn rest(&mut self) -> String {
+        let result = self.args[self.pos..].iter()
+            .map(|&(arg, quote)| match quote {
+                Some(q) => format!("{}{}{}", q, arg, q),
+                None => arg.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.pos = self.args.len();
+        result
     }
     /// Return the next argument in some parsed form.
     fn next<T: ParseArgs>(&mut self) -> Result<T, Error> {
@@ -84,7 +165,7 @@ impl<'a> Args<'a> {
     /// Return an error if there are arguments remaining.
     fn ensure_final(&self) -> Result<(), Error> {
         match self.args.get(self.pos) {
-            Some(s) => Err(Error::Extra(s.to_string())),
+            Some((s, _)) => Err(Error::Extra(s.to_string())),
             None => Ok(())
         }
     }
@@ -104,7 +185,7 @@ impl ParseArgs for Dir {
 
 impl ParseArgs for Instruction {
     fn from_args(args: &mut Args) -> Result<Self, Error> {
-        Instruction::from_symbol(args.next_raw().ok_or(Error::NoInstruction)?)
+        Instruction::from_symbol_lenient(args.next_raw().ok_or(Error::NoInstruction)?)
             .ok_or(Error::BadInstruction)
     }
 }
@@ -126,11 +207,19 @@ macro_rules! impl_ParseArgs_for_number {
     }
 }
 
+impl_ParseArgs_for_number!(u64);
 impl_ParseArgs_for_number!(usize);
 impl_ParseArgs_for_number!(u32);
 impl_ParseArgs_for_number!(u16);
 impl_ParseArgs_for_number!(u8);
 
+/// A string is parsed by taking all of the remaining arguments.
+impl ParseArgs for String {
+    fn from_args(args: &mut Args) -> Result<Self, Error> {
+        Ok(args.rest())
+    }
+}
+
 /// A value can optionally be parsed by returning `None` if there are no
 /// arguments remaining.
 impl<T: ParseArgs> ParseArgs for Option<T> {
@@ -158,6 +247,14 @@ impl<T: ParseArgs, U: ParseArgs> ParseArgs for (T, U) {
     }
 }
 
+/// A triple of values can be parsed from arguments by parsing each value
+/// in turn.
+impl<T: ParseArgs, U: ParseArgs, V: ParseArgs> ParseArgs for (T, U, V) {
+    fn from_args(args: &mut Args) -> Result<Self, Error> {
+        Ok((args.next()?, args.next()?, args.next()?))
+    }
+}
+
 /// A list of values can be parsed by repeatedly parsing until there are no
 /// arguments remaining.
 impl<T: ParseArgs> ParseArgs for Vec<T> {
@@ -195,4 +292,83 @@ impl<A, W, F> CommandHandler<W> for ClosureHandler<A, F>
         args.ensure_final()?;
         (self.f)(app, arg)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a complete argument string, as a command handler would.
+    fn parse<T: ParseArgs>(s: &str) -> Option<T> {
+        let mut args = Args::from_command(s);
+        let result = args.next().ok()?;
+        args.ensure_final().ok()?;
+        Some(result)
+    }
+
+    #[test]
+    fn gif_settings() {
+        type Settings = (PathBuf, Option<(u16, Option<u16>)>);
+        let cases = [
+            ("out.gif", (PathBuf::from("out.gif"), None)),
+            ("out.gif 50", (PathBuf::from("out.gif"), Some((50, None)))),
+            ("out.gif 50 2", (PathBuf::from("out.gif"), Some((50, Some(2))))),
+        ];
+        for (s, expected) in cases.iter() {
+            assert_eq!(parse::<Settings>(s).as_ref(), Some(expected));
+        }
+        // Formatting the parsed values gives back the same arguments.
+        for (s, _) in cases.iter() {
+            let (path, settings) = parse::<Settings>(s).unwrap();
+            let mut parts = vec![path.display().to_string()];
+            if let Some((frames, step)) = settings {
+                parts.push(frames.to_string());
+                parts.extend(step.map(|step| step.to_string()));
+            }
+            assert_eq!(&parts.join(" "), s);
+        }
+        assert!(parse::<Settings>("").is_none());
+        assert!(parse::<Settings>("out.gif x").is_none());
+        assert!(parse::<Settings>("out.gif 50 2 1").is_none());
+    }
+
+    #[test]
+    fn triples() {
+        type Triple = (PathBuf, Option<u16>, Option<u16>);
+        let path = || PathBuf::from("path");
+        assert_eq!(parse::<Triple>("path"), Some((path(), None, None)));
+        assert_eq!(parse::<Triple>("path 100"), Some((path(), Some(100), None)));
+        assert_eq!(parse::<Triple>("path 100 4"), Some((path(), Some(100), Some(4))));
+        assert_eq!(parse::<Triple>("path 100 4 2"), None);
+        assert_eq!(parse::<Triple>(""), None);
+        assert_eq!(
+            parse::<(Option<u16>, Option<u16>, Option<u16>)>(""),
+            Some((None, None, None)));
+        assert_eq!(parse::<(u32, usize, usize)>("1 2 3"), Some((1, 2, 3)));
+        assert_eq!(parse::<(u32, usize, usize)>("1 2"), None);
+    }
+
+    #[test]
+    fn quoted_arguments() {
+        assert_eq!(parse::<PathBuf>("\"my file.png\""), Some(PathBuf::from("my file.png")));
+        assert_eq!(
+            parse::<(PathBuf, u32)>("'a \"b\" c' 3"),
+            Some((PathBuf::from("a \"b\" c"), 3)));
+        assert_eq!(
+            parse::<(u8, String)>("2 echo \"two  words\""),
+            Some((2, "echo \"two  words\"".into())));
+        // An unterminated quote lasts until the end of the command.
+        assert_eq!(parse::<PathBuf>("\"no end"), Some(PathBuf::from("no end")));
+
+        let mut args = Args::from_command("repeat 2 export 'my file.png' 3");
+        assert_eq!(args.next_raw(), Some("repeat"));
+        assert_eq!(args.next_raw(), Some("2"));
+        let rest = args.rest();
+        assert_eq!(rest, "export 'my file.png' 3");
+        let mut nested = Args::from_command(&rest);
+        assert_eq!(nested.next_raw(), Some("export"));
+        assert_eq!(nested.next_raw(), Some("my file.png"));
+        assert_eq!(nested.next_raw(), Some("3"));
+        assert!(nested.is_end());
+    }
+}
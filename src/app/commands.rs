@@ -1,11 +1,15 @@
+use rand::Rng as _;
+
+use std::cmp::Ordering;
 use std::io::Write;
 use std::path::PathBuf;
 use std::rc::Rc;
 
-use crate::grid::{ORIGIN, Dir};
-use super::AppState;
-use super::command::{ClosureHandler, CommandHandler, Error};
-use super::instruction::Instruction;
+use crate::grid::{Point, ORIGIN, Dir};
+use super::{AppState, PhaseTimes, ABORT_CHECK_INTERVAL, MAX_CPS, MAX_FPS, asm};
+use super::command::{Args, ClosureHandler, CommandHandler, Error, ParseArgs};
+use super::instruction::{Instruction, Category};
+use super::organism::{KillPolicy, OrganismState};
 
 /// Convience macro to define a function that returns a CommandHandler
 /// trait object with given behavior.
@@ -24,8 +28,151 @@ define_command!(quit(app, ()) {
     Ok(())
 });
 
-define_command!(list(app, ()) {
-    app.ui.list_organisms(&app.organisms, app.focus);
+define_command!(force_quit(app, ()) {
+    app.quit = true;
+    app.force_quit = true;
+    Ok(())
+});
+
+/// A field that the organism list can be sorted by.
+pub(super) enum SortKey {
+    Ax,
+    Bx,
+    Ip,
+}
+
+impl SortKey {
+    fn compare(&self, a: &OrganismState, b: &OrganismState) -> Ordering {
+        match self {
+            SortKey::Ax => a.ax.cmp(&b.ax),
+            SortKey::Bx => a.bx.cmp(&b.bx),
+            SortKey::Ip => (a.ip.y, a.ip.x).cmp(&(b.ip.y, b.ip.x)),
+        }
+    }
+}
+
+impl ParseArgs for SortKey {
+    fn from_args(args: &mut Args) -> Result<Self, Error> {
+        match args.next_raw().ok_or(Error::NoSortKey)? {
+            "ax" => Ok(SortKey::Ax),
+            "bx" => Ok(SortKey::Bx),
+            "ip" => Ok(SortKey::Ip),
+            _ => Err(Error::BadSortKey),
+        }
+    }
+}
+
+impl ParseArgs for KillPolicy {
+    fn from_args(args: &mut Args) -> Result<Self, Error> {
+        let name = args.next_raw().ok_or(Error::NoKillPolicy)?;
+        KillPolicy::from_name(name).ok_or(Error::BadKillPolicy)
+    }
+}
+
+impl ParseArgs for Category {
+    fn from_args(args: &mut Args) -> Result<Self, Error> {
+        let name = args.next_raw().ok_or(Error::NoCategory)?;
+        Category::from_str(name).ok_or(Error::BadCategory)
+    }
+}
+
+/// An option changing which organisms are listed or in what order.
+pub(super) enum ListOption {
+    Dir(Dir),
+    Sort(SortKey),
+}
+
+impl ParseArgs for ListOption {
+    fn from_args(args: &mut Args) -> Result<Self, Error> {
+        // `list` only parses this if there are arguments remaining.
+        match args.next_raw().unwrap_or("") {
+            "dir" => Ok(ListOption::Dir(Dir::from_args(args)?)),
+            "sort" => Ok(ListOption::Sort(SortKey::from_args(args)?)),
+            s => Err(Error::BadListOption(s.to_string())),
+        }
+    }
+}
+
+define_command!(list(app, option => Option<ListOption>) {
+    match option {
+        None => app.ui.list_organisms(&app.organisms, app.focus, None, None),
+        Some(ListOption::Dir(dir)) => app.ui.list_organisms(
+            &app.organisms,
+            app.focus,
+            Some(&|o: &OrganismState| o.dir == dir),
+            None,
+        ),
+        Some(ListOption::Sort(key)) => app.ui.list_organisms(
+            &app.organisms,
+            app.focus,
+            None,
+            Some(&|a: &OrganismState, b: &OrganismState| key.compare(a, b)),
+        ),
+    }
+    Ok(())
+});
+
+define_command!(stats(app, ()) {
+    let num = app.organisms.len();
+    if num == 0 {
+        app.ui.info1("There are no living organisms.");
+        return Ok(());
+    }
+    let mut lines = vec![format!("{} living organisms.", num)];
+    if let Some(max_age) = app.organisms.max_age {
+        let remaining: Vec<u64> = app.organisms.iter()
+            .map(|ctx| (max_age as u64).saturating_sub(ctx.age))
+            .collect();
+        let mean = remaining.iter().sum::<u64>() as f64 / num as f64;
+        let min = remaining.iter().min().unwrap();
+        let max = remaining.iter().max().unwrap();
+        lines.push(format!("Remaining lifespan: mean {:.1}, min {}, max {}.", mean, min, max));
+    } else {
+        lines.push("Remaining lifespan: unlimited.".to_string());
+    }
+    if let Some(max_children) = app.organisms.max_children {
+        let total: usize = app.organisms.iter()
+            .map(|ctx| (max_children as usize).saturating_sub(ctx.num_children))
+            .sum();
+        lines.push(format!("Remaining children: mean {:.1}.", total as f64 / num as f64));
+    } else {
+        lines.push("Remaining children: unlimited.".to_string());
+    }
+    let dirs: &[Dir] = if app.grid.diagonals {
+        &[Dir::L, Dir::R, Dir::U, Dir::D, Dir::UL, Dir::UR, Dir::DL, Dir::DR]
+    } else {
+        &[Dir::L, Dir::R, Dir::U, Dir::D]
+    };
+    let counts: Vec<String> = dirs.iter().map(|&dir| {
+        let count = app.organisms.iter().filter(|ctx| ctx.organism.dir == dir).count();
+        format!("{} {}", dir.to_char(), count)
+    }).collect();
+    lines.push(format!("Directions: {}.", counts.join(", ")));
+    app.ui.info(lines);
+    Ok(())
+});
+
+define_command!(log(app, path => PathBuf) {
+    if path.as_os_str() == "off" {
+        if app.log.take().is_some() {
+            app.ui.info1("Stopped logging.");
+        } else {
+            app.ui.info1("Nothing is being logged.");
+        }
+        return Ok(());
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|_| Error::CannotWriteFile(path.clone()))?;
+    // Only write the header if we're not appending to an existing log.
+    let is_empty = file.metadata().map(|m| m.len() == 0).unwrap_or(false);
+    if is_empty {
+        writeln!(file, "cycle,population").map_err(|_| Error::CannotWriteFile(path.clone()))?;
+    }
+    app.log = Some(std::io::BufWriter::new(file));
+    app.ui.info1(format!("Logging the population to '{}'.", path.display()));
     Ok(())
 });
 
@@ -48,6 +195,16 @@ define_command!(set_max(app, new) {
     Ok(())
 });
 
+define_command!(kill_policy(app, policy => Option<KillPolicy>) {
+    if let Some(policy) = policy {
+        app.organisms.kill_policy = policy;
+        app.ui.info1(format!("Set the kill policy to {}.", policy.name()));
+    } else {
+        app.ui.info1(format!("The kill policy is {}.", app.organisms.kill_policy.name()));
+    }
+    Ok(())
+});
+
 define_command!(lifespan(app, ()) {
     if let Some(age) = app.organisms.max_age {
         app.ui.info1(format!("Organisms currently live for {} cycles.", age));
@@ -86,42 +243,281 @@ define_command!(set_max_children(app, new_max) {
     Ok(())
 });
 
-define_command!(speed(app, new) {
+define_command!(fuel(app, new_fuel) {
+    app.organisms.fuel = new_fuel;
+    if let Some(fuel) = new_fuel {
+        app.ui.info1(format!("New organisms can now execute {} instructions.", fuel));
+    } else {
+        app.ui.info1("New organisms can now execute arbitrarily many instructions.");
+    }
+    Ok(())
+});
+
+define_command!(energy(app, new_energy) {
+    app.organisms.energy = new_energy;
+    if let Some(energy) = new_energy {
+        app.ui.info1(format!("New organisms will now start with {} energy.", energy));
+    } else {
+        app.ui.info1("New organisms will now have unlimited energy.");
+    }
+    Ok(())
+});
+
+/// A new value for a setting, either given directly or relative to the
+/// current value with a leading `+` or `-`.
+pub(super) enum Adjustment {
+    Set(u32),
+    Increase(u32),
+    Decrease(u32),
+}
+
+impl Adjustment {
+    /// Apply the adjustment to the current value, saturating at the bounds
+    /// of `u32`.
+    fn apply(self, current: u32) -> u32 {
+        match self {
+            Adjustment::Set(n) => n,
+            Adjustment::Increase(n) => current.saturating_add(n),
+            Adjustment::Decrease(n) => current.saturating_sub(n),
+        }
+    }
+}
+
+impl ParseArgs for Adjustment {
+    fn from_args(args: &mut Args) -> Result<Self, Error> {
+        let arg = args.next_raw().ok_or(Error::NoNumber)?;
+        let parse = |s: &str| s.parse().map_err(|_| Error::BadNumber);
+        if let Some(n) = arg.strip_prefix('+') {
+            parse(n).map(Adjustment::Increase)
+        } else if let Some(n) = arg.strip_prefix('-') {
+            parse(n).map(Adjustment::Decrease)
+        } else {
+            parse(arg).map(Adjustment::Set)
+        }
+    }
+}
+
+define_command!(speed(app, new => Option<Adjustment>) {
     if let Some(new) = new {
+        let new = new.apply(app.config.cycle_frequency);
         if new == 0 {
             Err(Error::ZeroSpeed)
         } else {
             app.config.cycle_frequency = new;
+            app.config.cycles_per_second = None;
             app.ui.info1(format!("Set the simulation speed to {}ms/cycle.", new));
             Ok(())
         }
     } else {
-        app.ui.info1(format!(
-            "The current simulation speed is {}ms/cycle.",
-            app.config.cycle_frequency));
+        if let Some(cps) = app.config.cycles_per_second {
+            app.ui.info1(format!("The simulation is targeting {} cycles/second.", cps));
+        } else {
+            app.ui.info1(format!(
+                "The current simulation speed is {}ms/cycle.",
+                app.config.cycle_frequency));
+        }
         Ok(())
     }
 });
 
+define_command!(cps(app, new) {
+    if let Some(new) = new {
+        if new == 0 {
+            return Err(Error::ZeroSpeed);
+        }
+        let new = std::cmp::min(new, MAX_CPS);
+        app.config.cycles_per_second = Some(new);
+        app.ui.info1(format!("Set the simulation speed to {} cycles/second.", new));
+    } else {
+        let target = match app.config.cycles_per_second {
+            Some(cps) => format!("{} cycles/second", cps),
+            None => format!("{}ms/cycle", app.config.cycle_frequency),
+        };
+        match app.achieved_cps {
+            Some(achieved) => app.ui.info1(format!(
+                "Targeting {}; achieved {} cycles/second.", target, achieved)),
+            None => app.ui.info1(format!("Targeting {}.", target)),
+        }
+    }
+    Ok(())
+});
+
+define_command!(fps(app, new => Option<u32>) {
+    if let Some(new) = new {
+        if new == 0 {
+            return Err(Error::ZeroFrameRate);
+        }
+        let fps = std::cmp::min(new, MAX_FPS);
+        app.config.frame_frequency_ms = 1000 / fps;
+        app.ui.info1(format!("Set the frame rate to {} frames/second ({}ms/frame).",
+            fps, app.config.frame_frequency_ms));
+    } else {
+        app.ui.info1(format!("The frame rate is {} frames/second ({}ms/frame).",
+            1000 / app.config.frame_frequency_ms, app.config.frame_frequency_ms));
+    }
+    Ok(())
+});
+
 define_command!(seed(app, ()) {
     app.ui.info1(format!("The RNG seed is {}.", app.config.rng_seed));
     Ok(())
 });
 
+define_command!(reseed(app, seed => u64) {
+    app.reseed(seed);
+    app.ui.info1(format!("Set the RNG seed to {}.", seed));
+    Ok(())
+});
+
+define_command!(palette(app, path => PathBuf) {
+    let palette = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| Category::parse_palette(&s));
+    if let Some(palette) = palette {
+        app.config.palette = palette;
+        app.ui.palette = Some(palette);
+        app.ui.info1("Loaded palette.");
+        Ok(())
+    } else {
+        app.config.palette = Category::DEFAULT_PALETTE;
+        app.ui.palette = None;
+        Err(Error::BadPalette(path))
+    }
+});
+
+define_command!(info_height(app, new) {
+    if let Some(height) = new {
+        let height = app.ui.set_info_height(height);
+        app.ui.info1(format!("The info box is now {} lines tall.", height));
+    } else {
+        let height = app.ui.info_height();
+        app.ui.info1(format!("The info box is {} lines tall.", height));
+    }
+    Ok(())
+});
+
+define_command!(view_size(app, (width, height) => (u16, u16)) {
+    if width == 0 || height == 0 {
+        return Err(Error::NoNumber);
+    }
+    let (width, height) = app.ui.set_view_size(
+        width, height, app.grid.width(), app.grid.height());
+    app.ui.info1(format!("The view window is now {}x{}.", width, height));
+    Ok(())
+});
+
 define_command!(source(app, path => PathBuf) {
     app.run_commands_in_file(&path);
     Ok(())
 });
 
-define_command!(export(app, path) {
-    let result = app.write_image_data(path);
+define_command!(repeat(app, (times, command) => (usize, String)) {
+    if command.is_empty() {
+        return Err(Error::NoCommand);
+    }
+    for i in 0..times {
+        if i != 0 && app.abort_requested() {
+            app.ui.info1(format!("Aborted after {} repetitions.", i));
+            break;
+        }
+        app.run_nested_command(&command)?;
+    }
+    Ok(())
+});
+
+define_command!(if_empty(app, command => String) {
+    if command.is_empty() {
+        return Err(Error::NoCommand);
+    }
+    if app.organisms.len() == 0 {
+        app.run_nested_command(&command)?;
+    }
+    Ok(())
+});
+
+define_command!(if_alive(app, command => String) {
+    if command.is_empty() {
+        return Err(Error::NoCommand);
+    }
+    if app.organisms.len() != 0 {
+        app.run_nested_command(&command)?;
+    }
+    Ok(())
+});
+
+/// The optional arguments to `export`: a pixel scale and the keyword
+/// `legend`, in either order.
+pub(super) struct ExportOptions {
+    scale: Option<usize>,
+    legend: bool,
+}
+
+impl ParseArgs for ExportOptions {
+    fn from_args(args: &mut Args) -> Result<Self, Error> {
+        let mut options = ExportOptions { scale: None, legend: false };
+        while let Some(arg) = args.next_raw() {
+            if arg == "legend" && !options.legend {
+                options.legend = true;
+            } else if options.scale.is_none() {
+                options.scale = Some(arg.parse().map_err(|_| Error::BadNumber)?);
+            } else {
+                return Err(Error::Extra(arg.to_string()));
+            }
+        }
+        Ok(options)
+    }
+}
+
+define_command!(export(app, (path, options) => (PathBuf, ExportOptions)) {
+    let scale = options.scale.unwrap_or(1);
+    if scale == 0 {
+        return Err(Error::ZeroScale);
+    }
+    let result = app.write_image_data(path, scale, false, options.legend);
     if result.is_ok() {
         app.ui.info1("Exported.");
     }
     result
 });
 
-define_command!(export_gif(app, (path, settings) => (PathBuf, Option<(u16, Option<u16>)>)) {
+define_command!(export_overlay(app, (path, scale) => (PathBuf, Option<usize>)) {
+    let scale = scale.unwrap_or(1);
+    if scale == 0 {
+        return Err(Error::ZeroScale);
+    }
+    let result = app.write_image_data(path, scale, true, false);
+    if result.is_ok() {
+        app.ui.info1("Exported.");
+    }
+    result
+});
+
+define_command!(export_view(app, (path, scale) => (PathBuf, Option<usize>)) {
+    let scale = scale.unwrap_or(1);
+    if scale == 0 {
+        return Err(Error::ZeroScale);
+    }
+    let (view_width, view_height) = app.ui.view_size();
+    let cols = std::cmp::min(view_width as usize, app.grid.width());
+    let rows = std::cmp::min(view_height as usize, app.grid.height());
+    let start = app.ui.view_offset;
+    let result = app.write_region_image_data(path, scale, false, false, (start, cols, rows));
+    if result.is_ok() {
+        app.ui.info1("Exported.");
+    }
+    result
+});
+
+/// Shared implementation of the `export-gif`, `export-gif-trail`, and
+/// `export-gif-view` commands. If `view` is true, only the cells in the view
+/// window are included.
+fn export_gif_with_settings<W: Write>(
+    app: &mut AppState<W>,
+    path: PathBuf,
+    settings: Option<(u16, Option<u16>)>,
+    trail: bool,
+    view: bool,
+) -> Result<(), Error> {
     let (num_frames, step) = settings.unwrap_or((100, None));
     let step = step.unwrap_or(4);
     if num_frames == 0 {
@@ -130,12 +526,82 @@ define_command!(export_gif(app, (path, settings) => (PathBuf, Option<(u16, Optio
         Err(Error::ZeroStep)
     } else {
         app.ui.info1("Exporting...");
-        let result = app.write_gif_data(path, num_frames as usize, step as usize);
-        if result.is_ok() {
+        let (num_frames, step) = (num_frames as usize, step as usize);
+        let (frames, alert) = if view {
+            let (view_width, view_height) = app.ui.view_size();
+            let region = (
+                app.ui.view_offset,
+                std::cmp::min(view_width as usize, app.grid.width()),
+                std::cmp::min(view_height as usize, app.grid.height()),
+            );
+            app.write_region_gif_data(path, num_frames, step, trail, region)?
+        } else {
+            app.write_gif_data(path, num_frames, step, trail)?
+        };
+        if let Some(alert) = alert {
+            // Keep the trigger's alert visible above the result.
+            app.ui.info(vec![alert, format!("Exported {} of {} frames.", frames, num_frames)]);
+        } else if frames < num_frames {
+            app.ui.info1(format!("Aborted. Exported {} of {} frames.", frames, num_frames));
+        } else {
             app.ui.info1("Exported.");
         }
-        result
+        Ok(())
+    }
+}
+
+define_command!(export_gif(app, (path, settings) => (PathBuf, Option<(u16, Option<u16>)>)) {
+    export_gif_with_settings(app, path, settings, false, false)
+});
+
+define_command!(export_gif_trail(app, (path, settings) => (PathBuf, Option<(u16, Option<u16>)>)) {
+    export_gif_with_settings(app, path, settings, true, false)
+});
+
+define_command!(export_gif_view(app, (path, settings) => (PathBuf, Option<(u16, Option<u16>)>)) {
+    export_gif_with_settings(app, path, settings, false, true)
+});
+
+define_command!(export_heatmap(app, (path, cycles) => (PathBuf, usize)) {
+    app.ui.info1("Exporting...");
+    let (max_visits, ran, alert) = app.write_heatmap_data(path, cycles)?;
+    if let Some(alert) = alert {
+        app.ui.info(vec![alert, format!(
+            "Exported after {} cycles. The most visited cell was visited {} times.",
+            ran, max_visits)]);
+    } else if ran < cycles {
+        app.ui.info1(format!(
+            "Aborted after {} cycles. Exported. The most visited cell was visited {} times.",
+            ran, max_visits));
+    } else {
+        app.ui.info1(format!("Exported. The most visited cell was visited {} times.", max_visits));
     }
+    Ok(())
+});
+
+define_command!(export_ascii(app, (path, size) => (PathBuf, Option<(usize, usize)>)) {
+    let (start, cols, rows) = match size {
+        Some((width, height)) => {
+            let start = app.selection_start();
+            let cols = std::cmp::min(width, app.grid.width());
+            let rows = std::cmp::min(height, app.grid.height());
+            (start, cols, rows)
+        }
+        None => (ORIGIN, app.grid.width(), app.grid.height()),
+    };
+    let result = app.write_ascii_data(path, start, cols, rows);
+    if result.is_ok() {
+        app.ui.info1("Exported.");
+    }
+    result
+});
+
+define_command!(dump_json(app, path) {
+    let result = app.write_json_data(path);
+    if result.is_ok() {
+        app.ui.info1("Exported.");
+    }
+    result
 });
 
 define_command!(write_error_chance(app, new_chance) {
@@ -176,8 +642,9 @@ define_command!(wall_pierce_chance(app, new_chance) {
     Ok(())
 });
 
-define_command!(cosmic_ray_rate(app, new) {
+define_command!(cosmic_ray_rate(app, new => Option<Adjustment>) {
     if let Some(rate) = new {
+        let rate = rate.apply(app.config.cosmic_ray_rate);
         app.config.cosmic_ray_rate = rate;
         app.ui.info1(format!("Set cosmic rays to occur {} times per cycle.", rate));
     } else {
@@ -186,59 +653,411 @@ define_command!(cosmic_ray_rate(app, new) {
     Ok(())
 });
 
-define_command!(cycle(app, times) {
+define_command!(mutate(app, n => u32) {
+    let changed = (0..n).filter(|_| app.cosmic_ray()).count();
+    app.ui.info1(format!("Applied {} cosmic rays, changing {} cells.", n, changed));
+    Ok(())
+});
+
+define_command!(mutate_region(app, (n, width, height) => (u32, usize, usize)) {
+    if app.ui.selection().is_none() {
+        return Err(Error::NoSelection);
+    }
+    let region = app.selected_region(width, height);
+    if region.is_empty() {
+        app.ui.info1("The region is empty.");
+        return Ok(());
+    }
+    let mut changed = 0;
+    for _ in 0..n {
+        let idx = app.cosmic_ray_rng.gen_range(0, region.len());
+        if app.cosmic_ray_at(region[idx]) {
+            changed += 1;
+        }
+    }
+    app.ui.info1(format!("Applied {} cosmic rays, changing {} cells.", n, changed));
+    Ok(())
+});
+
+define_command!(soup(app, (width, height) => (usize, usize)) {
+    if app.ui.selection().is_none() {
+        return Err(Error::NoSelection);
+    }
+    let region = app.selected_region(width, height);
+    let (first, last) = match (region.first(), region.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => {
+            app.ui.info1("The region is empty.");
+            return Ok(());
+        }
+    };
+    for p in region {
+        let val = app.cosmic_ray_rng.gen();
+        app.edit(p, val);
+    }
+    app.ui.info1(format!("Randomized the cells from ({}, {}) to ({}, {}).",
+        first.x, first.y, last.x, last.y));
+    Ok(())
+});
+
+/// An option to `cycle` that redraws the screen every given number of cycles.
+pub(super) struct RenderEvery(u32);
+
+impl ParseArgs for RenderEvery {
+    fn from_args(args: &mut Args) -> Result<Self, Error> {
+        // `cycle` only parses this if there are arguments remaining.
+        match args.next_raw().unwrap_or("") {
+            "render" => Ok(RenderEvery(Option::<u32>::from_args(args)?.unwrap_or(1))),
+            s => Err(Error::BadCycleOption(s.to_string())),
+        }
+    }
+}
+
+define_command!(cycle(app, (times, render) => (Option<u32>, Option<RenderEvery>)) {
     if let Some(n) = times {
-        for _ in 0u32..n {
-            app.cycle();
+        let every = match render {
+            Some(RenderEvery(0)) => return Err(Error::ZeroStep),
+            Some(RenderEvery(every)) => Some(every),
+            None => None,
+        };
+        if let Some(every) = every {
+            for i in 1..=n {
+                // A trigger's alert is left in place of the usual message.
+                if app.cycle().is_some() {
+                    return Ok(());
+                }
+                if i % every == 0 {
+                    app.render();
+                    if app.abort_requested() {
+                        app.ui.info1(format!("Aborted after {} cycles.", i));
+                        return Ok(());
+                    }
+                }
+            }
+        } else {
+            let (ran, alert) = app.run_cycles(n);
+            if alert.is_some() {
+                return Ok(());
+            }
+            if ran < n {
+                app.ui.info1(format!("Aborted after {} cycles.", ran));
+                return Ok(());
+            }
         }
         app.ui.info1(format!("Ran {} cycles.", n));
-    } else {
-        app.cycle();
+    } else if app.cycle().is_none() {
         app.ui.info1("Ran a cycle.");
     }
     Ok(())
 });
 
+define_command!(wrap(app, ()) {
+    app.grid.wrap = !app.grid.wrap;
+    if app.grid.wrap {
+        app.ui.info1("The edges of the grid now wrap around.");
+    } else {
+        app.ui.info1("The edges of the grid now act as walls.");
+    }
+    Ok(())
+});
+
+/// Describe how a batch of `n` cycles ended after running `ran` of them.
+fn stop_verb(ran: u32, n: u32, alert: &Option<String>) -> &'static str {
+    if alert.is_some() {
+        "Paused after"
+    } else if ran < n {
+        "Aborted after"
+    } else {
+        "Ran"
+    }
+}
+
+define_command!(bench(app, n => u32) {
+    // Commands run between frames, so nothing is rendered while this runs.
+    let start = std::time::Instant::now();
+    let (ran, alert) = app.run_cycles(n);
+    let elapsed = start.elapsed().as_secs_f64();
+    let verb = stop_verb(ran, n, &alert);
+    let result = if elapsed > 0.0 {
+        format!(
+            "{} {} cycles in {:.3}s ({:.0} cycles/second).",
+            verb, ran, elapsed, ran as f64 / elapsed)
+    } else {
+        format!("{} {} cycles in {:.3}s.", verb, ran, elapsed)
+    };
+    // Keep a trigger's alert visible above the result.
+    app.ui.info(alert.into_iter().chain(std::iter::once(result)).collect());
+    Ok(())
+});
+
+define_command!(profile_phases(app, n => u32) {
+    app.phase_times = Some(PhaseTimes::default());
+    let start = std::time::Instant::now();
+    let mut ran = 0;
+    let mut alert = None;
+    while ran < n {
+        alert = app.cycle();
+        app.timed(|t| &mut t.render, |app| app.render());
+        ran += 1;
+        if alert.is_some() || (ran % ABORT_CHECK_INTERVAL == 0 && app.abort_requested()) {
+            break;
+        }
+    }
+    let total = start.elapsed();
+    let times = app.phase_times.take().unwrap();
+    let measured = times.organisms + times.cosmic_rays + times.dedup + times.render;
+    let verb = stop_verb(ran, n, &alert);
+    let line = |name: &str, time: std::time::Duration| {
+        let percent = if total.as_secs_f64() > 0.0 {
+            100.0 * time.as_secs_f64() / total.as_secs_f64()
+        } else {
+            0.0
+        };
+        format!("  {:12} {:9.3}s {:5.1}%", name, time.as_secs_f64(), percent)
+    };
+    let lines = vec![
+        format!("{} {} cycles in {:.3}s:", verb, ran, total.as_secs_f64()),
+        line("organisms", times.organisms),
+        line("cosmic rays", times.cosmic_rays),
+        line("dedup", times.dedup),
+        line("rendering", times.render),
+        line("other", total.saturating_sub(measured)),
+    ];
+    app.ui.info(alert.into_iter().chain(lines).collect());
+    Ok(())
+});
+
 define_command!(pause(app, ()) {
     app.toggle_pause();
     Ok(())
 });
 
+define_command!(pause_on_extinction(app, ()) {
+    app.config.pause_on_extinction = !app.config.pause_on_extinction;
+    if app.config.pause_on_extinction {
+        app.ui.info1("The simulation will pause when the last organism dies.");
+    } else {
+        app.ui.info1("The simulation will no longer pause when the last organism dies.");
+    }
+    Ok(())
+});
+
+define_command!(pause_at(app, threshold) {
+    app.pause_threshold = threshold;
+    if let Some(threshold) = threshold {
+        app.ui.info1(format!("The simulation will pause when there are {} organisms.", threshold));
+    } else {
+        app.ui.info1("Removed the population threshold.");
+    }
+    Ok(())
+});
+
+define_command!(stop_at(app, cycle) {
+    app.stop_cycle = cycle;
+    if let Some(cycle) = cycle {
+        app.ui.info1(format!("The simulation will pause at cycle {}.", cycle));
+    } else {
+        app.ui.info1("Removed the stopping cycle.");
+    }
+    Ok(())
+});
+
 define_command!(move_(app, (dir, times) => (Dir, Option<u16>)) {
-    app.ui.move_selection_n(dir, times.unwrap_or(1) as usize);
+    let (width, height) = (app.grid.width(), app.grid.height());
+    app.ui.move_selection_n(dir, times.unwrap_or(1) as usize, width, height);
     Ok(())
 });
 
 define_command!(write(app, ins => Instruction) {
     if let Some(selection) = app.ui.selection() {
-        app.grid.set(app.absolute(selection), ins as u8)
+        app.edit(selection, ins as u8);
     }
     Ok(())
 });
 
 define_command!(insert_line(app, instructions => Vec<Instruction>) {
-    let relative = app.ui.selection().unwrap_or(ORIGIN);
-    let mut pos = app.absolute(relative);
+    let start = app.selection_start();
+    let mut pos = start;
     for ins in instructions {
-        app.grid.set(pos, ins as u8);
+        app.edit(pos, ins as u8);
         pos = pos.right(app.grid.width());
     }
-    app.ui.select(Some(relative.down(app.grid.height())));
+    app.ui.select(Some(start.down(app.grid.height())));
+    Ok(())
+});
+
+define_command!(block(app, source => String) {
+    let mut rows = vec![Vec::new()];
+    for token in source.split_whitespace() {
+        if token == "/" {
+            rows.push(Vec::new());
+        } else {
+            let ins = Instruction::from_symbol_lenient(token)
+                .ok_or_else(|| Error::UnknownInstruction(token.to_string()))?;
+            rows.last_mut().unwrap().push(ins);
+        }
+    }
+    let start = app.selection_start();
+    let width = app.grid.width();
+    let height = app.grid.height();
+    for (dy, row) in rows.iter().enumerate() {
+        for (dx, &ins) in row.iter().enumerate() {
+            app.edit(start.right_n(dx, width).down_n(dy, height), ins as u8);
+        }
+    }
+    Ok(())
+});
+
+define_command!(assemble(app, path => PathBuf) {
+    let source = std::fs::read_to_string(&path)
+        .map_err(|_| Error::CannotReadFile(path))?;
+    let program = asm::assemble(&source)
+        .map_err(|e| Error::BadAssembly(e.line, e.message))?;
+    let start = app.selection_start();
+    let width = app.grid.width();
+    let height = app.grid.height();
+    for (dy, row) in program.iter().enumerate() {
+        for (dx, &cell) in row.iter().enumerate() {
+            if let Some(byte) = cell {
+                app.edit(start.right_n(dx, width).down_n(dy, height), byte);
+            }
+        }
+    }
+    app.ui.info1(format!("Assembled {} rows.", program.len()));
+    Ok(())
+});
+
+define_command!(disasm(app, (width, height) => (usize, usize)) {
+    let start = app.selection_start();
+    let width = std::cmp::min(width, app.grid.width());
+    let height = std::cmp::min(height, app.grid.height());
+    let lines = app.grid.view(start, width, height)
+        .map(|row| row
+            .map(|(_, byte)| Instruction::from_byte(byte).to_string())
+            .collect::<Vec<_>>()
+            .join(" "))
+        .collect();
+    app.ui.info(lines);
+    Ok(())
+});
+
+define_command!(hexdump(app, (width, height) => (usize, usize)) {
+    let start = app.selection_start();
+    let width = std::cmp::min(width, app.grid.width());
+    let height = std::cmp::min(height, app.grid.height());
+    let lines = app.grid.view(start, width, height)
+        .map(|row| {
+            let mut row = row.peekable();
+            let offset = match row.peek() {
+                Some((p, _)) => format!("{:4},{:4}:", p.x, p.y),
+                None => String::new(),
+            };
+            row.fold(offset, |line, (_, byte)| format!("{} {:02x}", line, byte))
+        })
+        .collect();
+    app.ui.info(lines);
     Ok(())
 });
 
 define_command!(byte(app, byte) {
     if let Some(selection) = app.ui.selection() {
-        app.grid.set(app.absolute(selection), byte);
+        app.edit(selection, byte);
+    }
+    Ok(())
+});
+
+define_command!(undo(app, ()) {
+    match app.undo_edits() {
+        Some(n) => app.ui.info1(format!("Restored {} cells.", n)),
+        None => app.ui.info1("There are no edits to undo."),
+    }
+    Ok(())
+});
+
+define_command!(redo(app, ()) {
+    match app.redo_edits() {
+        Some(n) => app.ui.info1(format!("Reapplied edits to {} cells.", n)),
+        None => app.ui.info1("There are no edits to redo."),
     }
     Ok(())
 });
 
+define_command!(decode(app, byte => u8) {
+    let ins = Instruction::from_byte(byte);
+    if Instruction::is_defined(byte) {
+        app.ui.info1(format!("{} is {} ({}), a {} instruction.",
+            byte, ins, ins.name(), ins.category().name()));
+    } else {
+        app.ui.info1(format!("{} is not an instruction, so it acts like {} ({}).",
+            byte, ins, ins.name()));
+    }
+    Ok(())
+});
+
+define_command!(encode(app, ins => Instruction) {
+    app.ui.info1(format!("{} ({}) is {}.", ins, ins.name(), ins as u8));
+    Ok(())
+});
+
 define_command!(spawn(app, ()) {
     app.spawn_organism();
     Ok(())
 });
 
+define_command!(spawn_at(app, (x, y) => (usize, usize)) {
+    let pos = Point { x: x % app.grid.width(), y: y % app.grid.height() };
+    app.organisms.insert(OrganismState::init(pos));
+    Ok(())
+});
+
+define_command!(seed_grid(app, (rows, cols, spacing) => (usize, usize, usize)) {
+    let start = app.selection_start();
+    let width = app.grid.width();
+    let height = app.grid.height();
+    // Reject sizes whose offsets would overflow, or which would place more
+    // organisms than there are cells.
+    let total = rows.checked_mul(cols).ok_or(Error::BadNumber)?;
+    if total > width * height
+        || rows.saturating_sub(1).checked_mul(spacing).is_none()
+        || cols.saturating_sub(1).checked_mul(spacing).is_none()
+    {
+        return Err(Error::BadNumber);
+    }
+    let mut spawned = 0;
+    'spawn: for row in 0..rows {
+        for col in 0..cols {
+            if let Some(max) = app.organisms.max {
+                if app.organisms.len() >= max {
+                    break 'spawn;
+                }
+            }
+            let pos = start
+                .right_n(col * spacing, width)
+                .down_n(row * spacing, height);
+            app.organisms.insert(OrganismState::init(pos));
+            spawned += 1;
+        }
+    }
+    if spawned < total {
+        app.ui.info1(format!("Reached the organism limit after spawning {} organisms.", spawned));
+    } else {
+        app.ui.info1(format!("Spawned {} organisms.", spawned));
+    }
+    Ok(())
+});
+
+define_command!(clear(app, size => Option<(usize, usize)>) {
+    let points = match size {
+        Some((width, height)) => app.selected_region(width, height),
+        None => app.grid.view_all().map(|(p, _)| p).collect(),
+    };
+    for &p in &points {
+        app.edit(p, Instruction::Nop as u8);
+    }
+    app.ui.info1(format!("Cleared {} cells.", points.len()));
+    Ok(())
+});
+
 define_command!(dedup(app, ()) {
     app.organisms.dedup();
     Ok(())
@@ -282,6 +1101,55 @@ define_command!(focus(app, idx) {
     Ok(())
 });
 
+define_command!(name(app, (idx, name) => (usize, String)) {
+    if let Some(id) = app.ui.get_listed_id(idx) {
+        if let Some(context) = app.organisms.get_mut(id) {
+            if name.is_empty() {
+                context.name = None;
+                app.ui.info1(format!("Removed the name of organism {}.", idx));
+            } else {
+                app.ui.info1(format!("Named organism {} '{}'.", idx, name));
+                context.name = Some(name);
+            }
+        } else {
+            app.ui.info1("That organism is no longer alive.");
+        }
+    } else {
+        app.ui.info1("Out of bounds.");
+    }
+    Ok(())
+});
+
+define_command!(watch(app, idx) {
+    if let Some(id) = app.ui.get_listed_id(idx) {
+        if !app.organisms.alive(id) {
+            app.ui.info1("That organism is no longer alive.");
+        } else if app.watch.contains(&id) {
+            app.ui.info1(format!("Already watching organism {}.", idx));
+        } else {
+            app.watch.push(id);
+            app.ui.info1(format!("Watching organism {}.", idx));
+        }
+    } else {
+        app.ui.info1("Out of bounds.");
+    }
+    Ok(())
+});
+
+define_command!(unwatch(app, idx) {
+    if let Some(id) = app.ui.get_listed_id(idx) {
+        if app.watch.contains(&id) {
+            app.watch.retain(|&watched| watched != id);
+            app.ui.info1(format!("Stopped watching organism {}.", idx));
+        } else {
+            app.ui.info1(format!("Organism {} is not being watched.", idx));
+        }
+    } else {
+        app.ui.info1("Out of bounds.");
+    }
+    Ok(())
+});
+
 define_command!(view(app, ()) {
     if let Some(context) = app.organisms.get_opt(app.focus) {
         app.ui.view_offset = context.organism.ip;
@@ -289,6 +1157,89 @@ define_command!(view(app, ()) {
     Ok(())
 });
 
+define_command!(inspect(app, ()) {
+    let ctx = app.organisms.get_opt(app.focus).ok_or(Error::NoFocus)?;
+    let o = &ctx.organism;
+    let mut lines = vec![
+        format!("Organism {}:", ctx.label()),
+        format!("ip=({}, {}) dir={} cursor=({}, {}) r={} flag={}",
+            o.ip.x, o.ip.y, o.dir.to_char(), o.cursor.x, o.cursor.y, o.r,
+            if o.flag { 't' } else { 'f' }),
+        format!("ax={} bx={} mut={} age={} children={}",
+            o.ax, o.bx, o.mutations, ctx.age, ctx.num_children),
+    ];
+    if let Some(energy) = o.energy {
+        lines.push(format!("energy={}", energy));
+    }
+    if let Some(fuel) = ctx.fuel {
+        lines.push(format!("fuel={}", fuel));
+    }
+    lines.push(String::from("Clipboard:"));
+    lines.extend(o.clipboard_rows().map(|row| row.iter()
+        .map(|&byte| Instruction::from_byte(byte).to_string())
+        .collect::<Vec<_>>()
+        .join(" ")));
+    app.ui.info(lines);
+    Ok(())
+});
+
+define_command!(center(app, ()) {
+    let selection = app.ui.selection().ok_or(Error::NoSelection)?;
+    let (width, height) = app.ui.view_size();
+    app.ui.view_offset = Point::from_modular(
+        selection.x as isize - width as isize / 2,
+        selection.y as isize - height as isize / 2,
+        app.grid.width(),
+        app.grid.height(),
+    );
+    Ok(())
+});
+
+define_command!(hide(app, category => Category) {
+    if app.ui.set_hidden(category, true) {
+        app.ui.info1(format!("Hid {} instructions.", category.name()));
+    } else {
+        app.ui.info1(format!("The {} instructions are already hidden.", category.name()));
+    }
+    Ok(())
+});
+
+define_command!(show(app, category => Category) {
+    if app.ui.set_hidden(category, false) {
+        app.ui.info1(format!("Showing {} instructions.", category.name()));
+    } else {
+        app.ui.info1(format!("The {} instructions are already shown.", category.name()));
+    }
+    Ok(())
+});
+
+define_command!(overview(app, ()) {
+    if app.ui.toggle_overview() {
+        app.ui.info1("Showing an overview of the whole grid.");
+    } else {
+        app.ui.info1("Showing the cells around the view offset.");
+    }
+    Ok(())
+});
+
+define_command!(minimap(app, ()) {
+    if app.ui.toggle_minimap() {
+        app.ui.info1("Showing the minimap.");
+    } else {
+        app.ui.info1("Hid the minimap.");
+    }
+    Ok(())
+});
+
+define_command!(ruler(app, ()) {
+    if app.ui.toggle_ruler() {
+        app.ui.info1("Showing the coordinate rulers.");
+    } else {
+        app.ui.info1("Hid the coordinate rulers.");
+    }
+    Ok(())
+});
+
 define_command!(move_ip(app, (dir, times) => (Dir, Option<u16>)) {
     if let Some(context) = app.organisms.get_opt_mut(app.focus) {
         let grid_width = app.grid.width();
@@ -300,12 +1251,13 @@ define_command!(move_ip(app, (dir, times) => (Dir, Option<u16>)) {
 });
 
 define_command!(run(app, instructions => Vec<Instruction>) {
+    let clock = app.total_cycles as u8;
     if let Some(context) = app.organisms.get_opt_mut(app.focus) {
         let mut tried_to_die = false;
         let mut new_organisms = Vec::new();
         for ins in instructions {
             use super::organism::Response;
-            match context.organism.run(&mut app.grid, ins) {
+            match context.organism.run(&mut app.grid, ins, clock) {
                 Response::Delay(_) => {}
                 Response::Fork(new) => new_organisms.push(new),
                 Response::Die => tried_to_die = true,
@@ -324,4 +1276,105 @@ define_command!(kill(app, ()) {
         app.organisms.remove(id);
     }
     Ok(())
-});
\ No newline at end of file
+});
+
+define_command!(kill_all(app, ()) {
+    let num_organisms = app.organisms.len();
+    app.organisms.clear();
+    app.focus = None;
+    app.ui.info1(format!("Killed {} organism{}.",
+        num_organisms,
+        if num_organisms == 1 { "" } else { "s" }));
+    Ok(())
+});
+
+define_command!(snapshot(app, ()) {
+    app.snapshot = Some(app.take_snapshot());
+    app.ui.info1(format!("Saved a snapshot at cycle {}.", app.total_cycles));
+    Ok(())
+});
+
+define_command!(restore(app, ()) {
+    if let Some(snapshot) = &app.snapshot {
+        let snapshot = snapshot.clone();
+        app.restore_snapshot(snapshot);
+        app.ui.info1(format!("Restored the snapshot from cycle {}.", app.total_cycles));
+    } else {
+        app.ui.info1("There is no snapshot to restore.");
+    }
+    Ok(())
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::app;
+    use crate::grid::Point;
+
+    /// Run a command that should succeed and return the info it displayed.
+    fn run<W: std::io::Write>(app: &mut AppState<W>, command: &str) -> Vec<String> {
+        assert!(app.run_command(command).is_ok(), "'{}' failed", command);
+        app.ui.info_lines().to_vec()
+    }
+
+    #[test]
+    fn lifespan_round_trip() {
+        let mut app = app(10, 10, &[]);
+        run(&mut app, "set-lifespan 50");
+        assert_eq!(app.organisms.max_age, Some(50));
+        assert_eq!(run(&mut app, "lifespan"), vec!["Organisms currently live for 50 cycles."]);
+        run(&mut app, "set-lifespan");
+        assert_eq!(app.organisms.max_age, None);
+        assert_eq!(run(&mut app, "lifespan"), vec!["There is currently no maximum lifetime."]);
+    }
+
+    #[test]
+    fn forks_without_child_limit() {
+        let mut app = app(10, 10, &[]);
+        app.grid.set_exact(Point { x: 0, y: 0 }, Instruction::FlagFork as u8);
+        run(&mut app, "set-max-children");
+        run(&mut app, "spawn");
+        run(&mut app, "cycle");
+        assert_eq!(app.organisms.len(), 2);
+
+        run(&mut app, "set-max-children 0");
+        app.grid.set_exact(Point { x: 1, y: 0 }, Instruction::FlagFork as u8);
+        run(&mut app, "cycle");
+        assert_eq!(app.organisms.len(), 2);
+    }
+
+    #[test]
+    fn set_max_caps_population() {
+        let mut app = app(10, 10, &["--fill=-="]);
+        run(&mut app, "set-max 3");
+        run(&mut app, "spawn");
+        for _ in 0..10 {
+            run(&mut app, "cycle");
+            assert!(app.organisms.len() <= 3);
+        }
+        assert_eq!(app.organisms.len(), 3);
+    }
+
+    #[test]
+    fn relative_adjustments() {
+        let mut app = app(10, 10, &[]);
+        run(&mut app, "cosmic-ray-rate 10");
+        assert_eq!(app.config.cosmic_ray_rate, 10);
+        run(&mut app, "cosmic-ray-rate +5");
+        assert_eq!(app.config.cosmic_ray_rate, 15);
+        run(&mut app, "cosmic-ray-rate -3");
+        assert_eq!(app.config.cosmic_ray_rate, 12);
+        run(&mut app, "cosmic-ray-rate -100");
+        assert_eq!(app.config.cosmic_ray_rate, 0);
+        assert!(app.run_command("cosmic-ray-rate +x").is_err());
+
+        run(&mut app, "speed 20");
+        run(&mut app, "speed -5");
+        assert_eq!(app.config.cycle_frequency, 15);
+        // Speeds saturate at zero, which is rejected.
+        assert!(matches!(app.run_command("speed -100"), Err(Error::ZeroSpeed)));
+        assert_eq!(app.config.cycle_frequency, 15);
+        run(&mut app, &format!("speed +{}", u32::MAX));
+        assert_eq!(app.config.cycle_frequency, u32::MAX);
+    }
+}
@@ -1,12 +1,59 @@
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
-use super::AppState;
+use rand::rngs::StdRng;
+use serde::Serialize;
+
+use crate::grid::{Grid, Point, ORIGIN};
+use super::{AppState, Config, ABORT_CHECK_INTERVAL, legend};
 use super::command::Error;
-use super::instruction::{Instruction, Category};
+use super::instruction::{Category, Instruction};
+use super::organism::{OrganismContext, OrganismId};
+
+/// The color of the focused organism's IP in exports with an organism overlay.
+const FOCUSED_RGB: [u8; 3] = [0xff, 0xff, 0x00];
+/// The color of other organisms' IPs in exports with an organism overlay.
+const ORGANISM_RGB: [u8; 3] = [0x00, 0x00, 0xff];
+
+/// The largest number of pixels in an exported PNG, which keeps the pixel
+/// data in memory to a reasonable size.
+const MAX_IMAGE_PIXELS: usize = 1 << 26;
+
+/// The largest number of pixels per unit at which the legend is drawn, so
+/// that it doesn't dwarf an image exported at a large scale.
+const LEGEND_MAX_SCALE: usize = 4;
+
+/// The number of frames for which a cell remains highlighted in a GIF with
+/// organism trails after an organism leaves it.
+const TRAIL_LENGTH: u8 = 4;
+/// The colors of organism trails, from most to least recently visited.
+const TRAIL_PALETTE: [u8; 3 * TRAIL_LENGTH as usize] = [
+    0x40, 0x40, 0xff,
+    0x30, 0x30, 0xc0,
+    0x20, 0x20, 0x80,
+    0x10, 0x10, 0x50,
+];
+// GIF palettes are limited to 256 colors: one per category, the focused and
+// unfocused organism colors, and the trail colors.
+const _: () = assert!(Category::ALL.len() + 2 + TRAIL_LENGTH as usize <= 256);
+
+/// The number of frames between updates of the progress shown while
+/// exporting a GIF.
+const GIF_PROGRESS_INTERVAL: usize = 10;
+
+/// A snapshot of the simulation state in a form that can be serialized.
+#[derive(Serialize)]
+struct Dump<'a> {
+    total_cycles: u64,
+    config: &'a Config,
+    grid: &'a Grid<StdRng>,
+    organisms: Vec<&'a OrganismContext>,
+    focus: Option<OrganismId>,
+}
 
 /// Encode a buffer of pixel data as a PNG file and write it to `w`.
 fn write_rgba_image_data(
@@ -22,20 +69,24 @@ fn write_rgba_image_data(
     encoder.write_header()?.write_image_data(data)
 }
 
-fn write_gif_data<'a>(
+/// Write a GIF whose frames are computed by `update_frame`, which can return
+/// `false` to stop early. Return the number of frames written.
+fn write_gif_data(
     path: &Path,
     width: u16,
     height: u16,
     num_frames: usize,
     palette: &[u8],
-    mut update_frame: impl FnMut(usize, &mut Vec<u8>),
-) -> std::io::Result<()> {
+    mut update_frame: impl FnMut(usize, &mut Vec<u8>) -> bool,
+) -> std::io::Result<usize> {
     use gif::SetParameter as _;
     let mut encoder = gif::Encoder::new(File::create(path)?, width, height, palette)?;
     encoder.set(gif::Repeat::Infinite)?;
     let mut frame_data = Vec::with_capacity(width as usize * height as usize * 4);
     for i in 0..num_frames {
-        update_frame(i, &mut frame_data);
+        if !update_frame(i, &mut frame_data) {
+            return Ok(i);
+        }
         encoder.write_frame(&gif::Frame {
             buffer: Cow::Borrowed(&frame_data),
             width,
@@ -43,53 +94,315 @@ fn write_gif_data<'a>(
             ..gif::Frame::default()
         })?;
     }
-    Ok(())
+    Ok(num_frames)
 }
 
 impl<W: Write> AppState<W> {
-    pub fn write_image_data(&mut self, path: PathBuf) -> Result<(), Error> {
+    pub fn write_image_data(
+        &mut self,
+        path: PathBuf,
+        pixel_scale: usize,
+        overlay: bool,
+        legend: bool,
+    ) -> Result<(), Error> {
+        let (width, height) = (self.grid.width(), self.grid.height());
+        self.write_region_image_data(path, pixel_scale, overlay, legend, (ORIGIN, width, height))
+    }
+    /// Write a PNG of the `cols` by `rows` rectangle of cells whose top-left
+    /// corner is `start`. If `legend` is set, a legend of the category colors
+    /// is added to the right of the image.
+    pub fn write_region_image_data(
+        &mut self,
+        path: PathBuf,
+        pixel_scale: usize,
+        overlay: bool,
+        legend: bool,
+        (start, cols, rows): (Point, usize, usize),
+    ) -> Result<(), Error> {
         if path.exists() {
             return Err(Error::ExportFileExists(path));
         }
 
+        let width = cols.checked_mul(pixel_scale).ok_or(Error::ImageTooBig)?;
+        let height = rows.checked_mul(pixel_scale).ok_or(Error::ImageTooBig)?;
+        match width.checked_mul(height) {
+            Some(pixels) if pixels <= MAX_IMAGE_PIXELS => {}
+            _ => return Err(Error::ImageTooBig),
+        }
+
         let file = File::create(&path).map_err(|_| Error::ExportFailure(path.clone()))?;
 
+        // Determine which points are highlighted by the overlay, if any.
+        let (occupied, focused_pos) = if overlay {
+            (
+                self.organisms.iter().map(|ctx| ctx.organism.ip).collect(),
+                self.organisms.get_opt(self.focus).map(|ctx| ctx.organism.ip),
+            )
+        } else {
+            (HashSet::new(), None)
+        };
+
+        let mut data = Vec::with_capacity(width * height * 4);
+        for row in self.grid.view(start, cols, rows) {
+            // Compute a single row of pixels and then repeat it vertically.
+            let mut row_data = Vec::with_capacity(width * 4);
+            for (pos, ins) in row {
+                let [r, g, b] = if occupied.contains(&pos) {
+                    if focused_pos == Some(pos) { FOCUSED_RGB } else { ORGANISM_RGB }
+                } else {
+                    self.config.palette[Instruction::from_byte(ins).category() as usize]
+                };
+                for _ in 0..pixel_scale {
+                    row_data.extend_from_slice(&[r, g, b, 0xff]);
+                }
+            }
+            for _ in 0..pixel_scale {
+                data.extend_from_slice(&row_data);
+            }
+        }
+
+        let (data, width, height) = if legend {
+            let scale = std::cmp::min(pixel_scale, LEGEND_MAX_SCALE);
+            legend::add_legend(&data, width, height, &self.config.palette, scale)
+        } else {
+            (data, width, height)
+        };
+
+        write_rgba_image_data(file, width, height, &data)
+            .map_err(|_| Error::ExportFailure(path))
+    }
+    /// Run `cycles` cycles, counting how many times an organism's IP is on
+    /// each cell, and write the counts as a heatmap PNG. Stop early if Esc is
+    /// pressed or a trigger pauses the simulation. Return the largest count,
+    /// the number of cycles run, and the trigger's alert if there was one.
+    pub fn write_heatmap_data(
+        &mut self,
+        path: PathBuf,
+        cycles: usize,
+    ) -> Result<(u32, usize, Option<String>), Error> {
+        if path.exists() {
+            return Err(Error::ExportFileExists(path));
+        }
+
         let width  = self.grid.width();
         let height = self.grid.height();
 
-        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
-        for ins in self.grid.view_all() {
-            let [r, g, b] = Instruction::from_byte(ins).category().color_rgb();
-            data.extend_from_slice(&[r, g, b, 0xff]);
+        let mut visits = vec![0u32; width * height];
+        let mut ran = 0;
+        let mut alert = None;
+        while ran < cycles && alert.is_none() {
+            if ran != 0 && ran % ABORT_CHECK_INTERVAL as usize == 0
+                && self.abort_requested() {
+                break;
+            }
+            ran += 1;
+            alert = self.cycle();
+            for ctx in self.organisms.iter() {
+                let ip = ctx.organism.ip;
+                visits[ip.y * width + ip.x] += 1;
+            }
+        }
+        let max_visits = visits.iter().copied().max().unwrap_or(0);
+
+        // Unvisited cells are black; visited cells range from gray to red
+        // depending on their count relative to the maximum.
+        let mut data = Vec::with_capacity(width * height * 4);
+        for &count in &visits {
+            if count == 0 {
+                data.extend_from_slice(&[0x00, 0x00, 0x00, 0xff]);
+            } else {
+                let t = count as f64 / max_visits as f64;
+                let r = (0x40 as f64 + t * 0xbf as f64) as u8;
+                let gb = (0x40 as f64 * (1.0 - t)) as u8;
+                data.extend_from_slice(&[r, gb, gb, 0xff]);
+            }
         }
 
+        let file = File::create(&path).map_err(|_| Error::ExportFailure(path.clone()))?;
         write_rgba_image_data(file, width, height, &data)
-            .map_err(|_| Error::ExportFailure(path))
+            .map_err(|_| Error::ExportFailure(path))?;
+        Ok((max_visits, ran, alert))
+    }
+    /// Increase the heat of every cell currently occupied by an organism to the maximum.
+    fn mark_trails(&self, heat: &mut [u8]) {
+        let width = self.grid.width();
+        for ctx in self.organisms.iter() {
+            let ip = ctx.organism.ip;
+            heat[ip.y * width + ip.x] = TRAIL_LENGTH;
+        }
     }
+    /// Write a GIF of the simulation, stopping early if Esc is pressed or a
+    /// trigger pauses the simulation. Return the number of frames written,
+    /// along with the trigger's alert if there was one.
     pub fn write_gif_data(
         &mut self,
         path: PathBuf,
         num_frames: usize,
-        step: usize
-    ) -> Result<(), Error> {
+        step: usize,
+        trail: bool,
+    ) -> Result<(usize, Option<String>), Error> {
+        let region = (ORIGIN, self.grid.width(), self.grid.height());
+        self.write_region_gif_data(path, num_frames, step, trail, region)
+    }
+    /// Like `write_gif_data`, but only include a region of the grid, given
+    /// as its top-left corner, width, and height.
+    pub fn write_region_gif_data(
+        &mut self,
+        path: PathBuf,
+        num_frames: usize,
+        step: usize,
+        trail: bool,
+        (start, cols, rows): (Point, usize, usize),
+    ) -> Result<(usize, Option<String>), Error> {
         // Make sure we're in a reasonable state
         if path.exists() {
             return Err(Error::ExportFileExists(path));
         }
-        let width: u16 = self.grid.width().try_into().map_err(|_| Error::WorldTooBig)?;
-        let height: u16 = self.grid.height().try_into().map_err(|_| Error::WorldTooBig)?;
+        if cols == 0 || rows == 0 {
+            return Err(Error::EmptyRegion);
+        }
+        let width: u16 = cols.try_into().map_err(|_| Error::WorldTooBig)?;
+        let height: u16 = rows.try_into().map_err(|_| Error::WorldTooBig)?;
+        let grid_width = self.grid.width();
+
+        // Organisms are drawn using colors appended after the category colors.
+        let mut palette: Vec<u8> = self.config.palette.iter().flatten().copied().collect();
+        let focused_idx = (palette.len() / 3) as u8;
+        palette.extend_from_slice(&FOCUSED_RGB);
+        let organism_idx = (palette.len() / 3) as u8;
+        palette.extend_from_slice(&ORGANISM_RGB);
+        let trail_start = (palette.len() / 3) as u8;
+        if trail {
+            palette.extend_from_slice(&TRAIL_PALETTE);
+        }
+        // How recently an organism has visited each cell, from 0 (not
+        // within the trail length) to `TRAIL_LENGTH` (currently occupied).
+        let mut heat = if trail {
+            vec![0u8; self.grid.width() * self.grid.height()]
+        } else {
+            Vec::new()
+        };
 
-        // Compute and write the frames
-        write_gif_data(&path, width, height, num_frames, &Category::PALETTE, |i, frame_data| {
+        // Compute and write the frames. The frame on which a trigger pauses
+        // the simulation is the last one.
+        let mut alert = None;
+        let frames = write_gif_data(&path, width, height, num_frames, &palette, |i, frame_data| {
+            if alert.is_some() || (i != 0 && self.abort_requested()) {
+                return false;
+            }
+            if i % GIF_PROGRESS_INTERVAL == 0 {
+                self.ui.info1(format!("Exporting... {}/{}", i, num_frames));
+                self.ui.flush();
+            }
+            if trail {
+                for h in &mut heat {
+                    *h = h.saturating_sub(1);
+                }
+                self.mark_trails(&mut heat);
+            }
             if i != 0 {
                 frame_data.clear();
                 for _ in 0..step {
-                    self.cycle();
+                    alert = self.cycle();
+                    if trail {
+                        self.mark_trails(&mut heat);
+                    }
+                    if alert.is_some() {
+                        break;
+                    }
                 }
             }
-            for ins in self.grid.view_all() {
-                frame_data.push(Instruction::from_byte(ins).category() as u8);
+            let occupied: HashSet<_> = self.organisms.iter()
+                .map(|ctx| ctx.organism.ip)
+                .collect();
+            let focused_pos = self.organisms.get_opt(self.focus).map(|ctx| ctx.organism.ip);
+            for (pos, ins) in self.grid.view(start, cols, rows).flatten() {
+                let idx = pos.y * grid_width + pos.x;
+                if occupied.contains(&pos) {
+                    frame_data.push(if focused_pos == Some(pos) { focused_idx } else { organism_idx });
+                } else if trail && heat[idx] != 0 {
+                    frame_data.push(trail_start + TRAIL_LENGTH - heat[idx]);
+                } else {
+                    frame_data.push(Instruction::from_byte(ins).category() as u8);
+                }
             }
-        }).map_err(|_| Error::ExportFailure(path))        
+            true
+        }).map_err(|_| Error::ExportFailure(path))?;
+        Ok((frames, alert))
+    }
+    /// Write the `cols` by `rows` rectangle of cells whose top-left corner
+    /// is `start` as text, with one character per cell representing the
+    /// category of its instruction.
+    pub fn write_ascii_data(
+        &mut self,
+        path: PathBuf,
+        start: Point,
+        cols: usize,
+        rows: usize,
+    ) -> Result<(), Error> {
+        if path.exists() {
+            return Err(Error::ExportFileExists(path));
+        }
+
+        let mut text = String::with_capacity((cols + 1) * rows);
+        for row in self.grid.view(start, cols, rows) {
+            text.extend(row.map(|(_, ins)| Instruction::from_byte(ins).category().ascii_char()));
+            text.push('\n');
+        }
+
+        std::fs::write(&path, text).map_err(|_| Error::ExportFailure(path))
+    }
+    pub fn write_json_data(&mut self, path: PathBuf) -> Result<(), Error> {
+        if path.exists() {
+            return Err(Error::ExportFileExists(path));
+        }
+
+        let file = File::create(&path).map_err(|_| Error::ExportFailure(path.clone()))?;
+
+        let dump = Dump {
+            total_cycles: self.total_cycles,
+            config: &self.config,
+            grid: &self.grid,
+            organisms: self.organisms.iter().collect(),
+            focus: self.focus,
+        };
+        serde_json::to_writer(std::io::BufWriter::new(file), &dump)
+            .map_err(|_| Error::ExportFailure(path))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{app, temp_path};
+
+    fn png_size(path: &Path) -> (u32, u32) {
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let (info, _) = decoder.read_info().unwrap();
+        (info.width, info.height)
+    }
+
+    #[test]
+    fn png_dimensions() {
+        let mut app = app(20, 10, &[]);
+        let path = temp_path("scaled.png");
+        assert!(app.write_image_data(path.clone(), 3, false, false).is_ok());
+        assert_eq!(png_size(&path), (60, 30));
+        std::fs::remove_file(&path).unwrap();
+
+        let path = temp_path("region.png");
+        let region = (Point { x: 15, y: 5 }, 8, 4);
+        assert!(app.write_region_image_data(path.clone(), 2, true, false, region).is_ok());
+        assert_eq!(png_size(&path), (16, 8));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn too_large_png() {
+        let mut app = app(20, 10, &[]);
+        let path = temp_path("huge.png");
+        assert!(matches!(app.write_image_data(path.clone(), usize::MAX, false, false), Err(Error::ImageTooBig)));
+        assert!(matches!(app.write_image_data(path.clone(), 1 << 12, false, false), Err(Error::ImageTooBig)));
+        assert!(!path.exists());
+    }
+}
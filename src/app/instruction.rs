@@ -1,6 +1,6 @@
 use super::ui::Color;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum Category {
     Special,
@@ -24,28 +24,86 @@ impl Category {
             Self::Memory      => Color::LightBlue,
         }
     }
-    pub fn color_rgb(self) -> [u8; 3] {
+    pub fn name(self) -> &'static str {
         match self {
-            Self::Special     => [0x30, 0x30, 0x30],
-            Self::Wall        => [0x8a, 0x8a, 0x8a],
-            Self::Calculation => [0x8e, 0xcd, 0x00],
-            Self::Control     => [0xc4, 0x6a, 0xe1],
-            Self::Cursor      => [0x00, 0xd4, 0xd9],
-            Self::Selection   => [0xe1, 0x00, 0x03],
-            Self::Memory      => [0x74, 0xa4, 0xdc],
+            Self::Special     => "special",
+            Self::Wall        => "wall",
+            Self::Calculation => "calculation",
+            Self::Control     => "control",
+            Self::Cursor      => "cursor",
+            Self::Selection   => "selection",
+            Self::Memory      => "memory",
         }
     }
-    pub const PALETTE: [u8; 3 * 7] = [
-        0x30, 0x30, 0x30,
-        0x8a, 0x8a, 0x8a,
-        0x8e, 0xcd, 0x00,
-        0xc4, 0x6a, 0xe1,
-        0x00, 0xd4, 0xd9,
-        0xe1, 0x00, 0x03,
-        0x74, 0xa4, 0xdc,
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|c| c.name() == s)
+    }
+    /// A single character representing instructions of this category in
+    /// ASCII exports.
+    pub fn ascii_char(self) -> char {
+        match self {
+            Self::Special     => '.',
+            Self::Wall        => '#',
+            Self::Calculation => '+',
+            Self::Control     => '>',
+            Self::Cursor      => '@',
+            Self::Selection   => '=',
+            Self::Memory      => '$',
+        }
+    }
+    /// Every category, in order.
+    pub const ALL: [Category; 7] = [
+        Self::Special,
+        Self::Wall,
+        Self::Calculation,
+        Self::Control,
+        Self::Cursor,
+        Self::Selection,
+        Self::Memory,
+    ];
+    /// The RGB colors of each category, indexed by category, used unless
+    /// another palette has been loaded.
+    pub const DEFAULT_PALETTE: Palette = [
+        [0x30, 0x30, 0x30],
+        [0x8a, 0x8a, 0x8a],
+        [0x8e, 0xcd, 0x00],
+        [0xc4, 0x6a, 0xe1],
+        [0x00, 0xd4, 0xd9],
+        [0xe1, 0x00, 0x03],
+        [0x74, 0xa4, 0xdc],
     ];
+    /// Parse a palette either as a JSON array of RGB triples or as lines of
+    /// three numbers separated by whitespace or commas. Blank lines and lines
+    /// starting with `#` are ignored in the latter format.
+    pub fn parse_palette(s: &str) -> Option<Palette> {
+        if let Ok(palette) = serde_json::from_str(s) {
+            return Some(palette);
+        }
+        let mut palette = Self::DEFAULT_PALETTE;
+        let mut lines = s.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'));
+        for color in &mut palette {
+            let mut components = lines.next()?
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|c| !c.is_empty());
+            for component in color.iter_mut() {
+                *component = components.next()?.parse().ok()?;
+            }
+            if components.next().is_some() {
+                return None;
+            }
+        }
+        if lines.next().is_some() {
+            return None;
+        }
+        Some(palette)
+    }
 }
 
+/// An RGB color for each instruction category.
+pub type Palette = [[u8; 3]; 7];
+
 macro_rules! gen_variant {
     (
         $enum_name:ident
@@ -66,6 +124,11 @@ macro_rules! gen_variant {
                     $(Self::$variant => Category::$category,)*
                 }
             }
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => stringify!($variant),)*
+                }
+            }
         }
         static $array_name: &[$enum_name] = &[$($enum_name::$variant,)*];
         static $symbol_array_name: &[&str] = &[$($symbol,)*];
@@ -81,7 +144,7 @@ macro_rules! gen_variant {
 }
 
 // Create the `Instruction` enum with methods that map each instruction
-// to its symbol or to its category.
+// to its symbol, its name, or its category.
 // Create a constant array of instructions in order and a static array
 // of instruction symbols which can be used to perform lookup.
 gen_variant! { Instruction (const INSTRUCTIONS, const INSTRUCTION_SYMBOLS)
@@ -183,14 +246,92 @@ gen_variant! { Instruction (const INSTRUCTIONS, const INSTRUCTION_SYMBOLS)
     CursorToB    "bm"  Selection
     Copy         "cm"  Selection
     Paste        "mc"  Selection
+
+    ClockToA  "at"  Special
+
+    MoveUL  "<^"  Control
+    MoveUR  ">^"  Control
+    MoveDL  "<v"  Control
+    MoveDR  ">v"  Control
+
+    MutFork  "~="  Special
+
+    CursorGoto  "#@"  Cursor
+    CursorXToA  "xa"  Cursor
+    CursorYToB  "yb"  Cursor
+
+    PasteResultToA  "ca"  Selection
+
+    HaltAtWall  "?#"  Control
 }
 
 impl Instruction {
     pub fn from_byte(b: u8) -> Self {
         INSTRUCTIONS.get(b as usize).copied().unwrap_or(Self::Nop)
     }
+    /// Determine whether a byte corresponds to an instruction. Other bytes
+    /// behave like `Nop`.
+    pub fn is_defined(b: u8) -> bool {
+        (b as usize) < INSTRUCTIONS.len()
+    }
     pub fn from_symbol(symbol: &str) -> Option<Self> {
         INSTRUCTION_SYMBOLS.iter().position(|&s| s == symbol)
             .map(|b| Self::from_byte(b as u8))
     }
-}
\ No newline at end of file
+    /// Like `from_symbol`, but ignore surrounding whitespace and, if only
+    /// one instruction matches, case. This is meant for symbols typed by
+    /// the user.
+    pub fn from_symbol_lenient(symbol: &str) -> Option<Self> {
+        let symbol = symbol.trim();
+        if let Some(ins) = Self::from_symbol(symbol) {
+            return Some(ins);
+        }
+        let mut matches = INSTRUCTION_SYMBOLS.iter()
+            .enumerate()
+            .filter(|(_, s)| s.eq_ignore_ascii_case(symbol))
+            .map(|(b, _)| b);
+        match (matches.next(), matches.next()) {
+            (Some(b), None) => Some(Self::from_byte(b as u8)),
+            _ => None,
+        }
+    }
+    /// The amount of energy it takes to execute the instruction. For `Copy`
+    /// and `Paste`, this is the cost per cell of the selection.
+    pub fn cost(self) -> u32 {
+        match self {
+            Self::Nop => 0,
+            _ => 1,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lenient(symbol: &str) -> Option<u8> {
+        Instruction::from_symbol_lenient(symbol).map(|ins| ins as u8)
+    }
+
+    #[test]
+    fn lenient_symbols() {
+        assert_eq!(lenient("0a"), Some(Instruction::ZeroA as u8));
+        assert_eq!(lenient("0A"), Some(Instruction::ZeroA as u8));
+        assert_eq!(lenient(" 0A\t"), Some(Instruction::ZeroA as u8));
+        assert_eq!(lenient("0B"), Some(Instruction::ZeroB as u8));
+        assert_eq!(lenient("A0"), Some(Instruction::IsZeroA as u8));
+        assert_eq!(lenient("AB"), Some(Instruction::CopyB as u8));
+        assert_eq!(lenient("BA"), Some(Instruction::CopyA as u8));
+        assert_eq!(lenient("0c"), None);
+        assert_eq!(lenient(""), None);
+    }
+
+    #[test]
+    fn symbols_are_distinct_ignoring_case() {
+        for (i, a) in INSTRUCTION_SYMBOLS.iter().enumerate() {
+            for b in &INSTRUCTION_SYMBOLS[i + 1..] {
+                assert!(!a.eq_ignore_ascii_case(b), "{} and {} differ only in case", a, b);
+            }
+            assert_eq!(lenient(&a.to_ascii_uppercase()), Some(i as u8));
+        }
+    }
+}
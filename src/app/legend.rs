@@ -0,0 +1,134 @@
+//! A legend labelling the color of each instruction category, drawn next to
+//! exported images.
+//!
+//! Labels are drawn with a small hardcoded bitmap font in which each glyph is
+//! 5 pixels wide and 7 pixels tall.
+
+use super::instruction::{Category, Palette};
+
+/// The color behind the legend and below an image shorter than it.
+const BACKGROUND_RGB: [u8; 3] = [0x00, 0x00, 0x00];
+/// The color of the labels.
+const TEXT_RGB: [u8; 3] = [0xff, 0xff, 0xff];
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+/// The space around the legend and between its entries.
+const PADDING: usize = 2;
+/// The length of the longest label, "CALCULATION".
+const MAX_LABEL_LEN: usize = 11;
+
+/// The width of the legend before scaling: a square as tall as a glyph and
+/// the longest label, with padding around and between them.
+const LEGEND_WIDTH: usize = PADDING + GLYPH_HEIGHT + PADDING + MAX_LABEL_LEN * (GLYPH_WIDTH + 1) + PADDING;
+/// The height of the legend before scaling: one row per category.
+const LEGEND_HEIGHT: usize = PADDING + Category::ALL.len() * (GLYPH_HEIGHT + PADDING);
+
+/// Return the rows of a glyph, with the leftmost pixel in the highest of the
+/// 5 low bits. Only the letters used in category names are defined.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c {
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'Y' => [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// An RGBA image being drawn on, in which each unit is a square of `scale`
+/// pixels.
+struct Canvas {
+    width: usize,
+    scale: usize,
+    data: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize, scale: usize) -> Self {
+        let mut data = Vec::with_capacity(width * height * scale * scale * 4);
+        for _ in 0..width * height * scale * scale {
+            data.extend_from_slice(&[BACKGROUND_RGB[0], BACKGROUND_RGB[1], BACKGROUND_RGB[2], 0xff]);
+        }
+        Self { width, scale, data }
+    }
+    fn fill(&mut self, x: usize, y: usize, [r, g, b]: [u8; 3]) {
+        let row_len = self.width * self.scale;
+        for py in y * self.scale..(y + 1) * self.scale {
+            for px in x * self.scale..(x + 1) * self.scale {
+                let idx = (py * row_len + px) * 4;
+                self.data[idx..idx + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+    }
+    fn draw_text(&mut self, x: usize, y: usize, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i * (GLYPH_WIDTH + 1);
+            for (dy, row) in glyph(c).iter().enumerate() {
+                for dx in 0..GLYPH_WIDTH {
+                    if row & (1 << (GLYPH_WIDTH - 1 - dx)) != 0 {
+                        self.fill(left + dx, y + dy, TEXT_RGB);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Return a copy of an RGBA image with a legend for `palette` added to its
+/// right, along with its new width and height. The legend is drawn at `scale`
+/// pixels per unit, and the image is extended downwards if it is shorter than
+/// the legend.
+pub fn add_legend(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    palette: &Palette,
+    scale: usize,
+) -> (Vec<u8>, usize, usize) {
+    let mut legend = Canvas::new(LEGEND_WIDTH, LEGEND_HEIGHT, scale);
+    for (i, &category) in Category::ALL.iter().enumerate() {
+        let y = PADDING + i * (GLYPH_HEIGHT + PADDING);
+        for dy in 0..GLYPH_HEIGHT {
+            for dx in 0..GLYPH_HEIGHT {
+                legend.fill(PADDING + dx, y + dy, palette[category as usize]);
+            }
+        }
+        let label = category.name().to_ascii_uppercase();
+        legend.draw_text(PADDING + GLYPH_HEIGHT + PADDING, y, &label);
+    }
+
+    let (legend_width, legend_height) = (LEGEND_WIDTH * scale, LEGEND_HEIGHT * scale);
+    let new_width = width + legend_width;
+    let new_height = std::cmp::max(height, legend_height);
+    let background = [BACKGROUND_RGB[0], BACKGROUND_RGB[1], BACKGROUND_RGB[2], 0xff];
+    let mut result = Vec::with_capacity(new_width * new_height * 4);
+    for y in 0..new_height {
+        if y < height {
+            result.extend_from_slice(&data[y * width * 4..(y + 1) * width * 4]);
+        } else {
+            for _ in 0..width {
+                result.extend_from_slice(&background);
+            }
+        }
+        if y < legend_height {
+            result.extend_from_slice(&legend.data[y * legend_width * 4..(y + 1) * legend_width * 4]);
+        } else {
+            for _ in 0..legend_width {
+                result.extend_from_slice(&background);
+            }
+        }
+    }
+    (result, new_width, new_height)
+}
@@ -1,6 +1,9 @@
 use rand::Rng;
 use rand::rngs::StdRng;
 
+use serde::Serialize;
+
+use std::cmp::Reverse;
 use std::collections::{HashSet, BTreeMap};
 
 mod state;
@@ -15,12 +18,16 @@ pub type OrganismId = u64;
 /// The organism's index in the list of living ones.
 type OrganismIdx = usize;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct OrganismContext {
     id: OrganismId,
     pub num_children: usize,
     pub age: u64,
     pub delay_cycles: u8,
+    /// The number of instructions the organism can still execute, if limited.
+    pub fuel: Option<u32>,
+    /// A name given to the organism by the user. This is not inherited by children.
+    pub name: Option<String>,
     pub organism: OrganismState,
 }
 
@@ -28,8 +35,49 @@ impl OrganismContext {
     pub fn id(&self) -> OrganismId {
         self.id
     }
+    /// Return the organism's name if it has one, and its ID otherwise.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("#{}", self.id),
+        }
+    }
+}
+
+/// How to choose which organism to kill when there are too many.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum KillPolicy {
+    /// Kill a uniformly random organism.
+    Random,
+    /// Kill the organism closest to the end of its lifespan.
+    Oldest,
+    /// Kill the organism that has lived the shortest time.
+    Youngest,
+    /// Kill the organism that has had the most children.
+    MostChildren,
+}
+
+impl KillPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            KillPolicy::Random => "random",
+            KillPolicy::Oldest => "oldest",
+            KillPolicy::Youngest => "youngest",
+            KillPolicy::MostChildren => "most-children",
+        }
+    }
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "random" => Some(KillPolicy::Random),
+            "oldest" => Some(KillPolicy::Oldest),
+            "youngest" => Some(KillPolicy::Youngest),
+            "most-children" => Some(KillPolicy::MostChildren),
+            _ => None,
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct OrganismCollection {
     /// The total number of organisms that have been created.
     next_id: OrganismId,
@@ -39,16 +87,23 @@ pub struct OrganismCollection {
     pub max_children: Option<u8>,
     /// The number of cycles that an organism is permitted to live.
     pub max_age: Option<u16>,
+    /// The number of instructions that a new organism is permitted to execute.
+    pub fuel: Option<u32>,
+    /// The energy given to organisms added without an energy limit.
+    pub energy: Option<u32>,
     /// `None` flags a dead organism.
     organisms: Vec<Option<OrganismContext>>,
     /// Mapping from IDs of living all organisms to their indices into the Vec.
     id_map: BTreeMap<OrganismId, OrganismIdx>,
     /// RNG used to determine which organism to kill.
     kill_rng: StdRng,
+    /// How to choose which organism to kill when there are too many.
+    pub kill_policy: KillPolicy,
 
     // Invariants:
     // - `len` is equal to the number of elements in `OrganismContext`.
     // - id_map contains `(id, idx)` if and only if `organisms[idx].is_some()` with that `id`.
+    // - `organisms` contains no `None` outside of `dedup`.
 }
 
 impl OrganismCollection {
@@ -63,21 +118,33 @@ impl OrganismCollection {
             num_children: 0,
             age: 0,
             delay_cycles: 0,
+            fuel: self.fuel,
+            name: None,
             organism: state
         }
     }
-    fn kill_random(&mut self) {
+    /// Kill a single organism chosen according to the kill policy.
+    fn kill_one(&mut self) {
         if self.len() == 0 {
             panic!("nothing to kill");
         }
-        loop {
-            let idx = self.kill_rng.gen_range(0, self.organisms.len());
-            if let Some(context) = &self.organisms[idx] {
-                let id = context.id;
-                self.remove(id);
-                break;
-            }
-        }
+        // Ties are broken in favor of the organism with the lowest ID.
+        let victim = match self.kill_policy {
+            KillPolicy::Random => return self.kill_random(),
+            KillPolicy::Oldest => self.iter().max_by_key(|ctx| (ctx.age, Reverse(ctx.id))),
+            KillPolicy::Youngest => self.iter().min_by_key(|ctx| (ctx.age, ctx.id)),
+            KillPolicy::MostChildren => self.iter()
+                .max_by_key(|ctx| (ctx.num_children, Reverse(ctx.id))),
+        };
+        let id = victim.unwrap().id;
+        self.remove(id);
+    }
+    /// Kill a uniformly random organism. Since the Vec is kept packed, every
+    /// slot holds a living organism and no retries are needed.
+    fn kill_random(&mut self) {
+        let idx = self.kill_rng.gen_range(0, self.organisms.len());
+        let id = self.organisms[idx].as_ref().expect("dead organism slot").id;
+        self.remove(id);
     }
     pub fn new(kill_rng: StdRng) -> Self {
         Self {
@@ -85,11 +152,18 @@ impl OrganismCollection {
             max: None,
             max_children: Some(4),
             max_age: Some(100),
+            fuel: None,
+            energy: None,
             organisms: Vec::new(),
             id_map: BTreeMap::new(),
             kill_rng,
+            kill_policy: KillPolicy::Random,
         }
     }
+    /// Replace the RNG used to determine which organism to kill.
+    pub fn set_kill_rng(&mut self, kill_rng: StdRng) {
+        self.kill_rng = kill_rng;
+    }
     pub fn len(&self) -> usize {
         self.id_map.len()
     }
@@ -110,27 +184,13 @@ impl OrganismCollection {
     pub fn get_opt_mut(&mut self, id: Option<OrganismId>) -> Option<&mut OrganismContext> {
         id.and_then(move |id| self.get_mut(id))
     }
-    pub fn insert(&mut self, state: OrganismState) {
-        let context = self.create_context(state);
-        let id = context.id;
-        let mut context = Some(context);
-        let mut created_idx = None;
-        for (idx, p) in self.organisms.iter_mut().enumerate() {
-            if p.is_none() {
-                // The compiler can't tell that either this block will run
-                // XOR the `unwrap_or_else` block will run, so we need to
-                // not move the context.
-                *p = context.take();
-                created_idx = Some(idx);
-                break;
-            }
+    pub fn insert(&mut self, mut state: OrganismState) {
+        if state.energy.is_none() {
+            state.energy = self.energy;
         }
-        let idx = created_idx.unwrap_or_else(|| {
-            let idx = self.organisms.len();
-            self.organisms.push(context);
-            idx
-        });
-        self.id_map.insert(id, idx);
+        let context = self.create_context(state);
+        self.id_map.insert(context.id, self.organisms.len());
+        self.organisms.push(Some(context));
     }
     pub fn remove(&mut self, id: OrganismId) {
         let idx = self.id_map.remove(&id).unwrap();
@@ -141,58 +201,79 @@ impl OrganismCollection {
             *self.id_map.get_mut(&replaced.id).unwrap() = idx;
         }
     }
+    /// Remove every organism. IDs are not reused afterwards.
+    pub fn clear(&mut self) {
+        self.organisms.clear();
+        self.id_map.clear();
+    }
     pub fn iter(&self) -> impl Iterator<Item=&OrganismContext> {
         self.id_map.values()
             .filter_map(move |&idx| self.organisms[idx].as_ref())
     }
-    /// Run a cycle for each organism, in arbitrary order.
-    pub fn run_cycle<R: Rng>(&mut self, grid: &mut Grid<R>, max_organisms: Option<usize>) {
+    /// Run a cycle for each organism, in order of ID so that runs don't depend
+    /// on how organisms happen to be laid out in memory. `clock` is the low
+    /// byte of the number of cycles that have passed.
+    pub fn run_cycle<R: Rng>(&mut self, grid: &mut Grid<R>, clock: u8) {
         let mut new = Vec::new();
         let mut suicides = Vec::new();
-        for context in &mut self.organisms {
-            if let Some(context) = context {
-                let id = context.id;
-                context.age += 1;
-                if let Some(max) = self.max_age {
-                    if context.age > max as u64 {
-                        suicides.push(id);
-                        continue;
-                    }
+        for &idx in self.id_map.values() {
+            let context = match &mut self.organisms[idx] {
+                Some(context) => context,
+                None => continue,
+            };
+            let id = context.id;
+            context.age += 1;
+            if let Some(max) = self.max_age {
+                if context.age > max as u64 {
+                    suicides.push(id);
+                    continue;
                 }
-                if context.delay_cycles != 0 {
-                    context.delay_cycles -= 1;
+            }
+            if context.delay_cycles != 0 {
+                context.delay_cycles -= 1;
+                continue;
+            }
+            if let Some(fuel) = &mut context.fuel {
+                if *fuel == 0 {
+                    suicides.push(id);
                     continue;
                 }
-                // Have the organism run the instruction and then handle its response.
-                let ins = Instruction::from_byte(grid[context.organism.ip]);
-                match context.organism.run(grid, ins) {
-                    Response::Delay(delay) => {
-                        context.delay_cycles = delay;
-                        context.organism.advance(grid);
-                    }
-                    Response::Fork(mut child) => {
-                        context.organism.advance(grid);
-                        context.num_children += 1;
-                        if let Some(max) = self.max_children {
-                            if context.num_children <= max as usize {
-                                child.advance(grid);
-                                new.push(child);
-                            }
-                        }
+                *fuel -= 1;
+            }
+            // Have the organism run the instruction and then handle its response.
+            let ins = Instruction::from_byte(grid[context.organism.ip]);
+            match context.organism.run(grid, ins, clock) {
+                Response::Delay(delay) => {
+                    context.delay_cycles = delay;
+                    if !context.organism.advance(grid) {
+                        suicides.push(id);
                     }
-                    Response::Die => {
+                }
+                Response::Fork(mut child) => {
+                    if !context.organism.advance(grid) {
                         suicides.push(id);
                     }
+                    context.num_children += 1;
+                    let allowed = match self.max_children {
+                        Some(max) => context.num_children <= max as usize,
+                        None => true,
+                    };
+                    if allowed && child.advance(grid) {
+                        new.push(child);
+                    }
+                }
+                Response::Die => {
+                    suicides.push(id);
                 }
             }
         }
         for id in suicides {
             self.remove(id);
         }
-        if let Some(max) = max_organisms {
+        if let Some(max) = self.max {
             let deaths_required = (self.len() + new.len()).saturating_sub(max);
             for _ in 0..deaths_required {
-                self.kill_random();
+                self.kill_one();
             }
         }
         for state in new {
@@ -209,5 +290,85 @@ impl OrganismCollection {
                 }
             }
         }
+        self.compact();
+    }
+    /// Remove the slots of organisms marked dead in place, moving living
+    /// organisms into them. Every other method keeps the Vec packed, so
+    /// afterwards it never contains `None`.
+    fn compact(&mut self) {
+        self.organisms.retain(Option::is_some);
+        for (idx, ctx) in self.organisms.iter().enumerate() {
+            if let Some(ctx) = ctx {
+                *self.id_map.get_mut(&ctx.id).unwrap() = idx;
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng as _;
+
+    use super::*;
+    use crate::grid::Point;
+
+    fn collection() -> OrganismCollection {
+        OrganismCollection::new(StdRng::seed_from_u64(0))
+    }
+
+    /// Check that the Vec is packed and agrees with `id_map`.
+    fn check_invariants(c: &OrganismCollection) {
+        assert_eq!(c.organisms.len(), c.id_map.len());
+        for (&id, &idx) in &c.id_map {
+            assert_eq!(c.organisms[idx].as_ref().map(|ctx| ctx.id), Some(id));
+        }
+    }
+
+    #[test]
+    fn churn_keeps_organisms_packed() {
+        let mut c = collection();
+        for round in 0..20 {
+            // Insert duplicates of a few organisms, and some distinct ones.
+            for i in 0..30 {
+                c.insert(OrganismState::init(Point { x: i % 3, y: round }));
+            }
+            c.dedup();
+            check_invariants(&c);
+            let ids: Vec<_> = c.iter().map(|ctx| ctx.id).step_by(2).collect();
+            for id in ids {
+                c.remove(id);
+                check_invariants(&c);
+            }
+            assert_eq!(c.organisms.len(), c.len());
+        }
+        assert!(c.len() <= 3 * 20);
+    }
+
+    #[test]
+    fn kill_random_is_uniform() {
+        const ORGANISMS: usize = 10;
+        const TRIALS: u64 = 10_000;
+        let mut base = collection();
+        for x in 0..ORGANISMS {
+            base.insert(OrganismState::init(Point { x, y: 0 }));
+        }
+        // Shuffle the slots so that IDs don't match indices.
+        base.remove(3);
+        base.insert(OrganismState::init(Point { x: 3, y: 1 }));
+        let ids: Vec<_> = base.iter().map(|ctx| ctx.id).collect();
+        let mut kills = vec![0; ORGANISMS];
+        for seed in 0..TRIALS {
+            let mut c = base.clone();
+            c.set_kill_rng(StdRng::seed_from_u64(seed));
+            c.kill_random();
+            check_invariants(&c);
+            let victim = ids.iter().position(|&id| !c.alive(id)).unwrap();
+            kills[victim] += 1;
+        }
+        // Each organism is expected to be killed 1000 times, with a standard
+        // deviation of 30.
+        for &count in &kills {
+            assert!((850..=1150).contains(&count), "kill counts {:?}", kills);
+        }
+    }
+}
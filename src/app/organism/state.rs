@@ -1,10 +1,15 @@
 use rand::Rng;
 
+use serde::Serialize;
+
 use std::mem::swap;
 
 use crate::grid::{Grid, Point, Dir};
 use super::Instruction;
 
+/// The energy gained by executing `Nop`.
+const NOP_ENERGY: u32 = 2;
+
 // Return the square root of an odd square number between 1 and 441.
 fn isqrt(n: usize) -> u8 {
     match n {
@@ -49,7 +54,7 @@ pub fn get_points_for_selection<R>(
     }))
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
 pub struct OrganismState {
     /// Instruction pointer
     pub ip: Point,
@@ -67,16 +72,24 @@ pub struct OrganismState {
     pub ax: u8,
     /// General-purpose register BX
     pub bx: u8,
+    /// The number of writes by this organism that were corrupted by write errors.
+    pub mutations: u32,
+    /// The energy available to execute instructions, if limited.
+    pub energy: Option<u32>,
+    /// The number of cells written by the last paste, saturating at 255.
+    pub pasted: u8,
 }
 
 impl std::fmt::Display for OrganismState {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}({}, {})\tax={} bx={}",
+        write!(f, "{}({}, {})\tax={} bx={} mut={} clip={}",
             self.dir.to_char(),
             self.ip.x,
             self.ip.y,
             self.ax,
-            self.bx
+            self.bx,
+            self.mutations,
+            selection_radius(&self.clipboard)
         )
     }
 }
@@ -92,10 +105,26 @@ impl OrganismState {
             flag: false,
             ax: 0,
             bx: 0,
+            mutations: 0,
+            energy: None,
+            pasted: 0,
         }
     }
-    pub fn advance<R>(&mut self, grid: &Grid<R>) {
-        self.ip = self.ip.move_in(self.dir, grid.width(), grid.height());
+    /// Return the rows of the square held in the clipboard.
+    pub fn clipboard_rows(&self) -> impl Iterator<Item=&[u8]> {
+        self.clipboard.chunks(isqrt(self.clipboard.len()) as usize)
+    }
+    /// Move the IP one step forward. Return `false` if it would leave a
+    /// bounded grid, in which case the organism should die as if it had hit
+    /// a wall.
+    pub fn advance<R>(&mut self, grid: &Grid<R>) -> bool {
+        match grid.move_in(self.ip, self.dir) {
+            Some(ip) => {
+                self.ip = ip;
+                true
+            }
+            None => false,
+        }
     }
     /// Attempt to set the selection radius. Do nothing if the proposed value is out of bounds.
     fn set_r(&mut self, new: u8) {
@@ -113,6 +142,20 @@ impl OrganismState {
         }
         do_set
     }
+    /// Attempt to move the cursor one step in a direction. The edges of a
+    /// bounded grid block the cursor like walls.
+    fn move_cursor<R: Rng>(&mut self, dir: Dir, grid: &Grid<R>) -> bool {
+        match grid.move_in(self.cursor, dir) {
+            Some(p) => self.try_set_cursor(p, grid),
+            None => false,
+        }
+    }
+    /// Write a value to the grid, recording whether it was corrupted.
+    fn write<R: Rng>(&mut self, grid: &mut Grid<R>, p: Point, val: u8) {
+        if grid.set(p, val) {
+            self.mutations = self.mutations.saturating_add(1);
+        }
+    }
     fn paste<R: Rng>(&mut self, grid: &mut Grid<R>) -> u8 {
         let r = selection_radius(&self.clipboard);
         let width = r * 2 + 1;
@@ -122,6 +165,7 @@ impl OrganismState {
         // Fill in the region using a flood fill to select relevant points.
         let mut frontier = vec![self.cursor];
         let mut modified = Vec::new();
+        let mut written = 0u8;
         while let Some(p) = frontier.pop() {
             if modified.contains(&p) {
                 continue;
@@ -130,39 +174,73 @@ impl OrganismState {
                 continue;
             }
             modified.push(p);
-            if grid[p] == Instruction::Wall as u8 {
-                if !grid.pierce_wall() {
-                    continue;
-                }
+            if grid[p] == Instruction::Wall as u8 && !grid.pierce_wall() {
+                continue;
             }
             let relative_pos = p.sub(low_corner, grid.width(), grid.height());
             let idx = relative_pos.x * (width as usize) + relative_pos.y;
-            grid.set(p, self.clipboard[idx]);
-            frontier.push(p.up(grid.height()));
-            frontier.push(p.down(grid.height()));
-            frontier.push(p.left(grid.width()));
-            frontier.push(p.right(grid.width()));
+            self.write(grid, p, self.clipboard[idx]);
+            written = written.saturating_add(1);
+            frontier.extend(grid.neighbors(p));
         }
+        self.pasted = written;
         width
     }
+    /// Pay the energy required to execute an instruction, if energy is
+    /// limited. Return `false` if there isn't enough.
+    fn pay_energy(&mut self, instruction: Instruction) -> bool {
+        let energy = match &mut self.energy {
+            Some(energy) => energy,
+            None => return true,
+        };
+        let side = 2 * self.r as u32 + 1;
+        let cost = match instruction {
+            Instruction::Copy => instruction.cost() * side * side,
+            Instruction::Paste => instruction.cost() * self.clipboard.len() as u32,
+            _ => instruction.cost(),
+        };
+        if *energy < cost {
+            return false;
+        }
+        *energy -= cost;
+        if let Instruction::Nop = instruction {
+            *energy = energy.saturating_add(NOP_ENERGY);
+        }
+        true
+    }
+    /// Split this organism's energy, if limited, with a newly forked child.
+    fn share_energy(&mut self, child: &mut OrganismState) {
+        if let Some(energy) = &mut self.energy {
+            let half = *energy / 2;
+            *energy -= half;
+            child.energy = Some(half);
+        }
+    }
     /// Execute the instruction. Return the number of additional cycles to delay
-    /// (usually 0). Return `None` if the organism should die.
-    pub fn run<R: Rng>(&mut self, grid: &mut Grid<R>, instruction: Instruction) -> Response {
+    /// (usually 0). Return `None` if the organism should die. `clock` is the low
+    /// byte of the number of cycles that have passed.
+    pub fn run<R: Rng>(
+        &mut self,
+        grid: &mut Grid<R>,
+        instruction: Instruction,
+        clock: u8,
+    ) -> Response {
         use Instruction::*;
         macro_rules! return_repeat_move {
             ($register:ident, $dir:ident) => {{
-                let width = grid.width();
-                let height = grid.height();
                 let mut i = 0;
                 while i < self.$register {
                     i += 1;
-                    if !self.try_set_cursor(self.cursor.move_in(Dir::$dir, width, height), grid) {
+                    if !self.move_cursor(Dir::$dir, grid) {
                         break;
                     }
                 }
                 return Response::Delay(i);
             }}
         }
+        if !self.pay_energy(instruction) {
+            return Response::Die;
+        }
         match instruction {
             Halt | Wall => return Response::Die,
             Nop => {}
@@ -170,13 +248,28 @@ impl OrganismState {
                 let mut new = self.clone();
                 new.flag = true;
                 self.flag = false;
+                self.share_energy(&mut new);
+                return Response::Fork(new);
+            },
+            MutFork => {
+                let mut new = self.clone();
+                new.flag = true;
+                self.flag = false;
+                self.share_energy(&mut new);
+                // Flip a random bit of a random byte of the child's clipboard.
+                let rng = grid.rng_mut();
+                let idx = rng.gen_range(0, new.clipboard.len());
+                let bit = rng.gen_range(0, 8);
+                new.clipboard[idx] ^= 1 << bit;
                 return Response::Fork(new);
             },
             CursorFork => {
                 let mut new = self.clone();
                 new.ip = new.cursor;
+                self.share_energy(&mut new);
                 return Response::Fork(new);
             },
+            ClockToA => self.ax = clock,
 
             ZeroA => self.ax = 0,
             ZeroB => self.bx = 0,
@@ -220,11 +313,25 @@ impl OrganismState {
             MoveR => self.dir = Dir::R,
             MoveU => self.dir = Dir::U,
             MoveD => self.dir = Dir::D,
+            MoveUL => if grid.diagonals { self.dir = Dir::UL }
+            MoveUR => if grid.diagonals { self.dir = Dir::UR }
+            MoveDL => if grid.diagonals { self.dir = Dir::DL }
+            MoveDR => if grid.diagonals { self.dir = Dir::DR }
             CondMoveL => if self.flag { self.dir = Dir::L }
             CondMoveR => if self.flag { self.dir = Dir::R }
             CondMoveU => if self.flag { self.dir = Dir::U }
             CondMoveD => if self.flag { self.dir = Dir::D }
             CondHalt => if self.flag { return Response::Die }
+            HaltAtWall => {
+                // The edge of a bounded grid counts as a wall.
+                let blocked = match grid.move_in(self.ip, self.dir) {
+                    Some(p) => grid[p] == Instruction::Wall as u8,
+                    None => true,
+                };
+                if blocked {
+                    return Response::Die;
+                }
+            }
             ReflectAll => self.set_dir(self.dir.reverse()),
             ReflectX => self.set_dir(self.dir.reflect_x()),
             ReflectY => self.set_dir(self.dir.reflect_y()),
@@ -242,10 +349,10 @@ impl OrganismState {
             FlagToA => self.ax = self.flag as u8,
             FlagToB => self.bx = self.flag as u8,
 
-            CursorL => { self.try_set_cursor(self.cursor.left(grid.width()), grid); }
-            CursorR => { self.try_set_cursor(self.cursor.right(grid.width()), grid); }
-            CursorU => { self.try_set_cursor(self.cursor.up(grid.height()), grid); }
-            CursorD => { self.try_set_cursor(self.cursor.down(grid.height()), grid); }
+            CursorL => { self.move_cursor(Dir::L, grid); }
+            CursorR => { self.move_cursor(Dir::R, grid); }
+            CursorU => { self.move_cursor(Dir::U, grid); }
+            CursorD => { self.move_cursor(Dir::D, grid); }
             CursorLTimesA => return_repeat_move!(ax, L),
             CursorRTimesA => return_repeat_move!(ax, R),
             CursorUTimesA => return_repeat_move!(ax, U),
@@ -255,6 +362,17 @@ impl OrganismState {
             CursorUTimesB => return_repeat_move!(bx, U),
             CursorDTimesB => return_repeat_move!(bx, D),
             CursorHome => { self.try_set_cursor(self.ip, grid); }
+            CursorGoto => {
+                let p = Point::from_modular(
+                    self.ax as isize,
+                    self.bx as isize,
+                    grid.width(),
+                    grid.height());
+                self.try_set_cursor(p, grid);
+            }
+            CursorXToA => self.ax = self.cursor.x as u8,
+            CursorYToB => self.bx = self.cursor.y as u8,
+            PasteResultToA => self.ax = self.pasted,
 
             RadiusA => self.set_r(self.ax),
             RadiusB => self.set_r(self.bx),
@@ -263,8 +381,8 @@ impl OrganismState {
             RadiusToB => self.bx = self.r,
             IncRadius => self.set_r(self.r + 1),
             DecRadius => self.set_r(self.r.saturating_sub(1)),
-            CursorA => grid.set(self.cursor, self.ax),
-            CursorB => grid.set(self.cursor, self.bx),
+            CursorA => self.write(grid, self.cursor, self.ax),
+            CursorB => self.write(grid, self.cursor, self.bx),
             CursorToA => self.ax = grid[self.cursor],
             CursorToB => self.bx = grid[self.cursor],
             Copy => self.clipboard = get_points_for_selection(self.cursor, self.r, grid)
@@ -273,4 +391,139 @@ impl OrganismState {
         }
         Response::Delay(0)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng as _;
+    use rand::rngs::StdRng;
+
+    use super::*;
+
+    fn grid(width: usize, height: usize) -> Grid<StdRng> {
+        Grid::init(width, height, StdRng::seed_from_u64(0), Some(Instruction::Nop as u8), 0)
+    }
+
+    fn at(x: usize, y: usize) -> Point {
+        Point { x, y }
+    }
+
+    /// Run an instruction, expecting it not to delay or end the organism.
+    fn run(org: &mut OrganismState, grid: &mut Grid<StdRng>, ins: Instruction) {
+        match org.run(grid, ins, 0) {
+            Response::Delay(_) => {}
+            _ => panic!("{} didn't continue", ins.name()),
+        }
+    }
+
+    #[test]
+    fn mut_fork_flips_one_bit() {
+        let mut g = grid(8, 8);
+        for (i, p) in get_points_for_selection(at(3, 3), 1, &g).enumerate().collect::<Vec<_>>() {
+            g.set_exact(p, i as u8 * 17);
+        }
+        for _ in 0..20 {
+            let mut org = OrganismState::init(at(3, 3));
+            org.r = 1;
+            run(&mut org, &mut g, Instruction::Copy);
+            let child = match org.run(&mut g, Instruction::MutFork, 0) {
+                Response::Fork(child) => child,
+                _ => panic!("MutFork didn't fork"),
+            };
+            assert_eq!(child.clipboard.len(), org.clipboard.len());
+            let flipped: u32 = child.clipboard.iter().zip(&org.clipboard)
+                .map(|(a, b)| (a ^ b).count_ones())
+                .sum();
+            assert_eq!(flipped, 1);
+        }
+    }
+
+    #[test]
+    fn cursor_goto_respects_walls() {
+        let mut g = grid(8, 8);
+        g.set_exact(at(3, 4), Instruction::Wall as u8);
+        let mut org = OrganismState::init(at(0, 0));
+        org.ax = 3;
+        org.bx = 4;
+        run(&mut org, &mut g, Instruction::CursorGoto);
+        assert_eq!(org.cursor, at(0, 0));
+        org.bx = 5;
+        run(&mut org, &mut g, Instruction::CursorGoto);
+        assert_eq!(org.cursor, at(3, 5));
+        // Coordinates wrap around the grid.
+        org.ax = 10;
+        run(&mut org, &mut g, Instruction::CursorGoto);
+        assert_eq!(org.cursor, at(2, 5));
+    }
+
+    #[test]
+    fn cursor_coordinates_round_trip() {
+        let mut g = grid(8, 8);
+        let mut org = OrganismState::init(at(0, 0));
+        org.ax = 6;
+        org.bx = 2;
+        run(&mut org, &mut g, Instruction::CursorGoto);
+        run(&mut org, &mut g, Instruction::CursorHome);
+        assert_eq!(org.cursor, at(0, 0));
+        org.cursor = at(6, 2);
+        org.ax = 0;
+        org.bx = 0;
+        run(&mut org, &mut g, Instruction::CursorXToA);
+        run(&mut org, &mut g, Instruction::CursorYToB);
+        assert_eq!((org.ax, org.bx), (6, 2));
+        org.cursor = at(1, 1);
+        run(&mut org, &mut g, Instruction::CursorGoto);
+        assert_eq!(org.cursor, at(6, 2));
+    }
+
+    /// Set up an organism with a full 3x3 clipboard whose cursor is walled
+    /// in on all four sides.
+    fn walled_paste() -> (OrganismState, Grid<StdRng>) {
+        let mut g = grid(8, 8);
+        let mut org = OrganismState::init(at(3, 3));
+        org.r = 1;
+        run(&mut org, &mut g, Instruction::Copy);
+        for &p in &g.neighbors(at(3, 3)).collect::<Vec<_>>() {
+            g.set_exact(p, Instruction::Wall as u8);
+        }
+        (org, g)
+    }
+
+    #[test]
+    fn paste_stops_at_walls() {
+        let (mut org, mut g) = walled_paste();
+        g.wall_pierce_chance = 0;
+        run(&mut org, &mut g, Instruction::Paste);
+        assert_eq!(org.pasted, 1);
+        for p in g.neighbors(at(3, 3)).collect::<Vec<_>>() {
+            assert_eq!(g[p], Instruction::Wall as u8);
+        }
+        run(&mut org, &mut g, Instruction::PasteResultToA);
+        assert_eq!(org.ax, 1);
+    }
+
+    #[test]
+    fn paste_pierces_walls() {
+        let (mut org, mut g) = walled_paste();
+        g.wall_pierce_chance = 1;
+        run(&mut org, &mut g, Instruction::Paste);
+        assert_eq!(org.pasted, 9);
+        for p in get_points_for_selection(at(3, 3), 1, &g).collect::<Vec<_>>() {
+            assert_eq!(g[p], Instruction::Nop as u8);
+        }
+    }
+
+    #[test]
+    fn halt_at_wall() {
+        let mut g = grid(8, 8);
+        let mut org = OrganismState::init(at(2, 2));
+        run(&mut org, &mut g, Instruction::HaltAtWall);
+        g.set_exact(at(3, 2), Instruction::Wall as u8);
+        assert!(matches!(org.run(&mut g, Instruction::HaltAtWall, 0), Response::Die));
+
+        // The edge of a bounded grid counts as a wall.
+        let mut org = OrganismState::init(at(7, 0));
+        run(&mut org, &mut g, Instruction::HaltAtWall);
+        g.wrap = false;
+        assert!(matches!(org.run(&mut g, Instruction::HaltAtWall, 0), Response::Die));
+    }
+}
@@ -1,16 +1,30 @@
 use rand::Rng;
 
-use std::io::{Read, Write};
+use std::io::Write;
+use std::cmp::Ordering;
 use std::collections::HashSet;
+use std::time::Instant;
 
 use crate::grid::{Grid, Dir, Point, ORIGIN};
 use super::organism::{
     OrganismCollection,
+    OrganismContext,
     OrganismState,
     OrganismId,
     get_points_for_selection
 };
-use super::instruction::Instruction;
+use super::instruction::{Instruction, Category, Palette};
+
+/// The maximum number of cells along each side of the minimap.
+const MINIMAP_SIZE: usize = 16;
+/// The number of frames between recomputations of the minimap's colors.
+const MINIMAP_REFRESH_FRAMES: u32 = 30;
+
+/// Determine whether any of the values `lo..hi` are among the `len` values
+/// starting at `start` and wrapping around modulo `modulus`.
+fn overlaps_modular(lo: usize, hi: usize, start: usize, len: usize, modulus: usize) -> bool {
+    (lo..hi).any(|i| (i + modulus - start) % modulus < len)
+}
 
 /// Enum representing different colors.
 #[derive(Clone, Copy)]
@@ -25,6 +39,7 @@ pub enum Color {
     Yellow,
     Blue,
     Gray,
+    Rgb([u8; 3]),
     Reset,
     None,
 }
@@ -43,6 +58,7 @@ impl Color {
             Color::Yellow       => format!("{}", color::Fg(color::Yellow)),
             Color::Blue         => format!("{}", color::Fg(color::Blue)),
             Color::Gray         => format!("{}", color::Fg(color::AnsiValue::grayscale(4))),
+            Color::Rgb([r, g, b]) => format!("{}", color::Fg(color::Rgb(r, g, b))),
             Color::Reset        => format!("{}", color::Fg(color::Reset)),
             Color::None         => String::new(),
         }
@@ -60,18 +76,94 @@ impl Color {
             Color::Yellow       => format!("{}", color::Bg(color::Yellow)),
             Color::Blue         => format!("{}", color::Bg(color::Blue)),
             Color::Gray         => format!("{}", color::Bg(color::AnsiValue::grayscale(4))),
+            Color::Rgb([r, g, b]) => format!("{}", color::Bg(color::Rgb(r, g, b))),
             Color::Reset        => format!("{}", color::Bg(color::Reset)),
             Color::None         => String::new(),
         }
     }
 }
 
+/// Split a string into ANSI escape sequences and individual visible characters.
+/// Escape sequences are returned as `Err` and characters as `Ok`.
+fn ansi_chunks(s: &str) -> impl Iterator<Item=Result<char, &str>> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let c = rest.chars().next()?;
+        if rest.starts_with("\x1b[") {
+            // A CSI sequence ends with a byte in the range 0x40..=0x7e.
+            let len = rest[2..].find(|c| ('\x40'..='\x7e').contains(&c))
+                .map_or(rest.len(), |i| i + 3);
+            let (seq, tail) = rest.split_at(len);
+            rest = tail;
+            Some(Err(seq))
+        } else {
+            rest = &rest[c.len_utf8()..];
+            Some(Ok(c))
+        }
+    })
+}
+
+/// Return the number of columns a string occupies, ignoring escape sequences.
+fn visible_width(s: &str) -> usize {
+    ansi_chunks(s).filter(Result::is_ok).count()
+}
+
+/// Split a line into pieces that each occupy at most `width` columns. Each
+/// continuation line begins with the escape sequences that preceded it so
+/// that it retains the same colors.
+fn wrap_line(line: &str, width: usize) -> Vec<String> {
+    if width == 0 || visible_width(line) <= width {
+        return vec![line.to_string()];
+    }
+    let mut lines = Vec::new();
+    let mut escapes = String::new();
+    let mut current = String::new();
+    let mut current_width = 0;
+    for chunk in ansi_chunks(line) {
+        match chunk {
+            Err(seq) => {
+                escapes.push_str(seq);
+                current.push_str(seq);
+            }
+            Ok(c) => {
+                if current_width == width {
+                    lines.push(std::mem::replace(&mut current, escapes.clone()));
+                    current_width = 0;
+                }
+                current.push(c);
+                current_width += 1;
+            }
+        }
+    }
+    lines.push(current);
+    lines
+}
+
+/// A predicate determining which organisms are listed.
+pub type OrganismFilter<'a> = &'a dyn Fn(&OrganismState) -> bool;
+/// A comparison function determining the order in which organisms are listed.
+pub type OrganismComparator<'a> = &'a dyn Fn(&OrganismState, &OrganismState) -> Ordering;
+
+/// Simulation values shown at the top of the status box, which change from
+/// frame to frame.
+pub(super) struct Status {
+    pub total_cycles: u64,
+    pub paused: bool,
+    pub num_organisms: usize,
+    /// The achieved cycles per second, if they fall short of the target.
+    pub lagging_cps: Option<u32>,
+    pub selected_byte: Option<u8>,
+}
+
 /// General information relevant to the UI but not the simulation.
 pub(super) struct UI<W> {
     /// Handle to raw mode STDOUT.
     stdout: Option<W>,
-    /// The position of the point currently selected.
+    /// The position in the grid of the point currently selected.
     selection: Option<Point>,
+    /// The position relative to the view window at which the selection
+    /// delimiters are currently drawn.
+    selection_mark: Option<Point>,
     /// The width of the viewing window, separate from the grid itself.
     view_width: u16,
     /// The height of the viewing window, separate from the grid itself.
@@ -87,9 +179,33 @@ pub(super) struct UI<W> {
     info_box_scroll_offset: usize,
     /// The number of lines currently taken up by the status box on the right.
     status_box_height: u16,
+    /// The index of the first line in the status box that is being displayed.
+    status_scroll_offset: usize,
     /// IDs of organisms in the order they were displayed last time they were
     /// listed.
     list_order: Vec<OrganismId>,
+    /// The palette used to color instructions using RGB colors, if one has
+    /// been loaded. Otherwise, the terminal's own colors are used.
+    pub palette: Option<Palette>,
+    /// Whether to emit color escape codes at all.
+    color_enabled: bool,
+    /// Commands previously entered at the command line, oldest first.
+    command_history: Vec<String>,
+    /// Whether coordinate rulers are displayed along the view window.
+    ruler_enabled: bool,
+    /// Whether the minimap is displayed.
+    minimap_enabled: bool,
+    /// The average color of each cell of the minimap, in row-major order.
+    minimap: Vec<[u8; 3]>,
+    /// The number of frames until the minimap's colors are recomputed.
+    minimap_countdown: u32,
+    /// Whether the view window shows the whole grid with one character per
+    /// block of cells instead of the cells around the view offset.
+    overview_enabled: bool,
+    /// Categories of instructions that are drawn as blank cells.
+    hidden_categories: HashSet<Category>,
+    /// When the UI was created, used to show the elapsed wall-clock time.
+    start_time: Instant,
 }
 
 /// Convenience macro to write to STDOUT.
@@ -135,7 +251,7 @@ impl<W: Write> UI<W> {
         // The info box is placed 3 lines below the view window, but we add 1
         // to handle the gutter above the view window and 1 to compensate for
         // the coordinates being 1-based.
-        let start_y = (self.view_height as u16) + 5;
+        let start_y = self.view_height + 5;
         for line_no in 0..self.info_box_view_height {
             let term_y = start_y + line_no;
             // Clear the previous line
@@ -148,6 +264,22 @@ impl<W: Write> UI<W> {
             }
         }
     }
+    /// Replace the contents of the command line with a new command.
+    fn redraw_command(&mut self, command: &str) {
+        self.go_to(2, self.view_height + 3);
+        self.clear_right();
+        print!(self, ": {}", command);
+        self.flush();
+    }
+    /// Clear the area of the screen taken up by the view window.
+    fn clear_view(&mut self) {
+        for vis_y in 0..self.view_height {
+            self.go_to(2, vis_y + 2);
+            let blank = " ".repeat(self.view_width as usize * 3 + 1);
+            print!(self, blank);
+        }
+        self.selection_mark = None;
+    }
     /// Render two given characters around a point.
     fn render_delimiters(&mut self, p: Point, start: char, end: char) {
         let term_x = (p.x as u16) * 3 + 2;
@@ -159,22 +291,64 @@ impl<W: Write> UI<W> {
     }
 }
 
+// Color helpers that respect whether color is enabled.
+impl<W> UI<W> {
+    /// Return the escape code to set the foreground color, if color is enabled.
+    fn fg(&self, color: Color) -> String {
+        if self.color_enabled { color.fg() } else { String::new() }
+    }
+    /// Return the escape code to set the background color, if color is enabled.
+    fn bg(&self, color: Color) -> String {
+        if self.color_enabled { color.bg() } else { String::new() }
+    }
+    /// Return the color used to draw instructions of a given category.
+    fn category_color(&self, category: Category) -> Color {
+        match &self.palette {
+            Some(palette) => Color::Rgb(palette[category as usize]),
+            None => category.color(),
+        }
+    }
+}
+
 // Public getters and setters.
 impl<W> UI<W> {
     pub fn selection(&self) -> Option<Point> {
         self.selection
     }
+    pub fn has_stdout(&self) -> bool {
+        self.stdout.is_some()
+    }
+    pub fn view_size(&self) -> (u16, u16) {
+        (self.view_width, self.view_height)
+    }
+    pub fn info_height(&self) -> u16 {
+        self.info_box_view_height
+    }
+    #[cfg(test)]
+    pub fn info_lines(&self) -> &[String] {
+        &self.info_box
+    }
+    #[cfg(test)]
+    pub fn selection_mark(&self) -> Option<Point> {
+        self.selection_mark
+    }
 }
 
 // Public methods related to UI rendering.
 impl<W: Write> UI<W> {
-    pub fn new(stdout: Option<W>, view_width: u16, view_height: u16) -> Self {
+    pub fn new(
+        stdout: Option<W>,
+        view_width: u16,
+        view_height: u16,
+        color_enabled: bool,
+    ) -> Self {
         // TODO: compute view_width, view_height, and info_box_view_height
         // based on the data termion provides about the width and height
         // of the terminal.
         let mut ui = Self {
             stdout,
             selection: None,
+            selection_mark: None,
             view_width,
             view_height,
             view_offset: ORIGIN,
@@ -182,7 +356,18 @@ impl<W: Write> UI<W> {
             info_box_view_height: 10,
             info_box_scroll_offset: 0,
             status_box_height: 0,
+            status_scroll_offset: 0,
             list_order: Vec::new(),
+            palette: None,
+            color_enabled,
+            command_history: Vec::new(),
+            ruler_enabled: false,
+            minimap_enabled: false,
+            minimap: Vec::new(),
+            minimap_countdown: 0,
+            overview_enabled: false,
+            hidden_categories: HashSet::new(),
+            start_time: Instant::now(),
         };
         ui.clear();
         ui
@@ -196,17 +381,78 @@ impl<W: Write> UI<W> {
     /// Clear the screen.
     pub fn clear(&mut self) {
         print!(self, termion::clear::All);
+        self.selection_mark = None;
     }
-    /// Replace and redraw the existing info message.
+    /// Replace and redraw the existing info message. Lines that are too
+    /// wide for the terminal are wrapped.
     pub fn info(&mut self, info: Vec<String>) {
         self.info_box_scroll_offset = 0;
-        self.info_box = info;
+        // The info box starts in the second column.
+        let width = match (&self.stdout, termion::terminal_size()) {
+            (Some(_), Ok((term_width, _))) => term_width.saturating_sub(1) as usize,
+            _ => 0,
+        };
+        self.info_box = info.iter().flat_map(|line| wrap_line(line, width)).collect();
         self.render_info_box();
     }
     /// Replace the existing info message with a single-line one.
     pub fn info1<S: Into<String>>(&mut self, info: S) {
         self.info(vec![info.into()]);
     }
+    /// Change the number of lines of the info box that can be displayed and
+    /// redraw it. The height is kept between 1 and the number of lines left
+    /// below the view window in the terminal. Return the resulting height.
+    pub fn set_info_height(&mut self, height: u16) -> u16 {
+        let start_y = self.view_height + 5;
+        let mut height = height.max(1);
+        if self.stdout.is_some() {
+            if let Ok((_, term_height)) = termion::terminal_size() {
+                height = height.min(term_height.saturating_sub(start_y - 1).max(1));
+            }
+        }
+        // Clear any lines that will no longer be part of the info box.
+        for line_no in height..self.info_box_view_height {
+            self.go_to(2, start_y + line_no);
+            self.clear_right();
+        }
+        self.info_box_view_height = height;
+        let max_offset = self.info_box.len().saturating_sub(height as usize);
+        self.info_box_scroll_offset = self.info_box_scroll_offset.min(max_offset);
+        self.render_info_box();
+        height
+    }
+    /// Change the dimensions of the view window and redraw the whole screen.
+    /// The dimensions are kept within the grid and the space available in
+    /// the terminal. Return the resulting dimensions.
+    pub fn set_view_size(
+        &mut self,
+        width: u16,
+        height: u16,
+        grid_width: usize,
+        grid_height: usize,
+    ) -> (u16, u16) {
+        let clamp = |n: u16, max: usize| std::cmp::min(n as usize, max).max(1) as u16;
+        let mut width = clamp(width, grid_width);
+        let mut height = clamp(height, grid_height);
+        if self.stdout.is_some() {
+            if let Ok((term_width, term_height)) = termion::terminal_size() {
+                // Each cell takes 3 columns, and the view window needs a
+                // gutter on each side. Below it are the command line and at
+                // least one line of the info box.
+                width = width.min((term_width.saturating_sub(3) / 3).max(1));
+                height = height.min(term_height.saturating_sub(5).max(1));
+            }
+        }
+        self.view_width = width;
+        self.view_height = height;
+        self.status_box_height = 0;
+        self.status_scroll_offset = 0;
+        self.minimap_countdown = 0;
+        self.clear();
+        self.render_info_box();
+        self.render_selection(grid_width, grid_height);
+        (width, height)
+    }
     /// Scroll the info box upwards one line and redraw.
     pub fn info_scroll_up(&mut self) {
         self.info_box_scroll_offset = self.info_box_scroll_offset.saturating_sub(1);
@@ -222,18 +468,36 @@ impl<W: Write> UI<W> {
         self.render_info_box();
     }
     /// Display a color-coded list of living organisms in the info box.
-    pub fn list_organisms(&mut self, organisms: &OrganismCollection, focus: Option<OrganismId>) {
+    /// Only organisms satisfying `filter` are listed, and if `compare` is
+    /// passed, they are listed in the order it defines.
+    pub fn list_organisms(
+        &mut self,
+        organisms: &OrganismCollection,
+        focus: Option<OrganismId>,
+        filter: Option<OrganismFilter>,
+        compare: Option<OrganismComparator>,
+    ) {
         let mut lines = vec![String::from("Organisms:")];
         let mut list_order = Vec::new();
-        for (i, state) in organisms.iter().enumerate() {
+        let mut listed: Vec<_> = organisms.iter()
+            .filter(|ctx| match filter {
+                Some(f) => f(&ctx.organism),
+                None => true,
+            })
+            .collect();
+        if let Some(compare) = compare {
+            listed.sort_by(|a, b| compare(&a.organism, &b.organism));
+        }
+        for (i, state) in listed.into_iter().enumerate() {
             let id = state.id();
             list_order.push(id);
             let color = if Some(id) == focus { Color::Yellow } else { Color::Blue };
-            lines.push(format!("{color}{i}: {o}{reset}",
-                color = color.fg(),
+            lines.push(format!("{color}{i}: {label} {o}{reset}",
+                color = self.fg(color),
                 i = i,
+                label = state.label(),
                 o = state.organism,
-                reset = Color::Reset.fg()
+                reset = self.fg(Color::Reset)
             ));
         }
         if lines.len() == 1 {
@@ -247,33 +511,66 @@ impl<W: Write> UI<W> {
     pub fn get_listed_id(&mut self, index: usize) -> Option<OrganismId> {
         self.list_order.get(index).copied()
     }
-    /// Replace the previous selection with a new selection and redraw it.
+    /// Replace the previous selection with a new point in the grid. The
+    /// delimiters are redrawn the next time the grid is rendered.
     pub fn select(&mut self, new_selection: Option<Point>) {
-        if let Some(p) = self.selection {
+        self.selection = new_selection;
+    }
+    /// Move the selection delimiters to wherever the selected point appears
+    /// in the view window, hiding them if it isn't visible.
+    fn render_selection(&mut self, grid_width: usize, grid_height: usize) {
+        let mark = self.selection.and_then(|p| p.in_view(
+            self.view_offset,
+            self.view_width as usize,
+            self.view_height as usize,
+            grid_width,
+            grid_height,
+        ));
+        if mark == self.selection_mark {
+            return;
+        }
+        if let Some(p) = self.selection_mark {
             self.render_delimiters(p, ' ', ' ');
         }
-        if let Some(p) = new_selection {
+        if let Some(p) = mark {
             self.render_delimiters(p, '[', ']');
         }
-        self.selection = new_selection;
+        self.selection_mark = mark;
     }
-    /// Move the selection in a particular direction and redraw it.
-    pub fn move_selection(&mut self, dir: Dir) {
-        let pos = self.selection.map(|p| p.move_in(
-            dir,
-            self.view_width as usize,
-            self.view_height as usize,
-        )).unwrap_or(ORIGIN);
-        self.select(Some(pos));
+    /// Select the cell at a 1-based terminal position, inverting the layout
+    /// used by `render_grid`. Positions outside the view window are ignored.
+    pub fn click(&mut self, term_x: u16, term_y: u16, grid_width: usize, grid_height: usize) {
+        // Each cell is 3 columns wide, including the delimiter to its left.
+        if term_x < 2 || term_y < 2 || self.overview_enabled {
+            return;
+        }
+        let x = (term_x - 2) / 3;
+        let y = term_y - 2;
+        if x < self.view_width && y < self.view_height {
+            self.select(Some(Point {
+                x: (self.view_offset.x + x as usize) % grid_width,
+                y: (self.view_offset.y + y as usize) % grid_height,
+            }));
+        }
     }
-    /// Move the selection `n` spaces in a particular direction and redraw it.
-    pub fn move_selection_n(&mut self, dir: Dir, n: usize) {
-        let pos = self.selection.map(|p| p.move_in_n(
-            dir,
-            n,
+    /// Move the selection `n` spaces in a particular direction, starting
+    /// from the top-left corner of the view if nothing is selected. If the
+    /// selection leaves the view window, the view is scrolled along with it.
+    pub fn move_selection_n(&mut self, dir: Dir, n: usize, grid_width: usize, grid_height: usize) {
+        let pos = match self.selection {
+            Some(p) => p.move_in_n(dir, n, grid_width, grid_height),
+            None => self.view_offset,
+        };
+        let visible = pos.in_view(
+            self.view_offset,
             self.view_width as usize,
             self.view_height as usize,
-        )).unwrap_or(ORIGIN);
+            grid_width,
+            grid_height,
+        ).is_some();
+        if !visible {
+            self.view_offset = self.view_offset.move_in_n(dir, n, grid_width, grid_height);
+        }
         self.select(Some(pos));
     }
     /// Move the view offset in a particular direction. There is no need to redraw it because that
@@ -284,44 +581,260 @@ impl<W: Write> UI<W> {
     /// Render the status box data.
     pub fn render_status_box(
         &mut self,
-        total_cycles: u64,
-        num_organisms: usize,
-        selected_byte: Option<u8>,
-        focused_organism: Option<&OrganismState>,
+        status: Status,
+        focused_organism: Option<&OrganismContext>,
+        watched_organisms: Vec<&OrganismContext>,
     ) {
-        let term_x = self.view_width as u16 * 3 + 3;
+        let term_x = self.view_width * 3 + 3;
         let term_y = 2;
         // Clear the previous status box
         for i in 0..self.status_box_height {
             self.go_to(term_x, term_y + i);
             self.clear_right();
         }
-        let mut status_lines = 0;
-        /// Convenience macro to write a line of status.
+        let mut lines = Vec::new();
+        /// Convenience macro to add a line of status.
         macro_rules! write_line {
             () => {
-                status_lines += 1;
+                lines.push(String::new());
             };
             ($fmt:literal$(, $fmt_arg:expr)*) => {
-                self.go_to(term_x + 1, term_y + status_lines);
-                print!(self, $fmt $(, $fmt_arg)*);
-                status_lines += 1;
+                lines.push(format!($fmt $(, $fmt_arg)*));
             }
         }
-        write_line!("{:10}", total_cycles);
+        // The clock shares a line with the cycle count so that the lines
+        // below don't move. It keeps running while paused, so it is marked
+        // instead.
+        let Status { total_cycles, paused, num_organisms, lagging_cps, selected_byte } = status;
+        let secs = self.start_time.elapsed().as_secs();
+        write_line!("{:10} {} {:02}:{:02}:{:02}",
+            total_cycles,
+            if paused { 'p' } else { ' ' },
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60);
         write_line!("#{:9}", num_organisms);
+        if let Some(cps) = lagging_cps {
+            write_line!("c/s {:6}", cps);
+        }
         if let Some(byte) = selected_byte {
             write_line!("byte   {:3}", byte);
+            if !Instruction::is_defined(byte) {
+                write_line!("   unknown");
+            }
         }
-        if let Some(o) = focused_organism {
-            let OrganismState { dir, ax, bx, flag, .. } = o;
+        if let Some(ctx) = focused_organism {
+            write_line!("{:>10}", ctx.label());
+            let OrganismState { dir, ax, bx, flag, mutations, .. } = &ctx.organism;
             write_line!("dir      {}", dir.to_char());
             write_line!("ax     {:3}", ax);
             write_line!("bx     {:3}", bx);
             write_line!("flag     {}", if *flag { 't' } else { 'f' });
+            write_line!("mut {:6}", mutations);
+            if let Some(fuel) = ctx.fuel {
+                write_line!("fuel {:5}", fuel);
+            }
+            if let Some(energy) = ctx.organism.energy {
+                write_line!("nrg {:6}", energy);
+            }
+        }
+        if !watched_organisms.is_empty() {
+            write_line!();
+            for ctx in watched_organisms {
+                let o = &ctx.organism;
+                write_line!("{} {} {:3} {:3}", ctx.label(), o.dir.to_char(), o.ax, o.bx);
+            }
+        }
+        // Only render as many lines as fit in the terminal, starting from the
+        // scroll offset.
+        let visible = match (&self.stdout, termion::terminal_size()) {
+            (Some(_), Ok((_, term_height))) => term_height.saturating_sub(term_y - 1) as usize,
+            _ => lines.len(),
+        };
+        let max_offset = lines.len().saturating_sub(visible);
+        self.status_scroll_offset = std::cmp::min(self.status_scroll_offset, max_offset);
+        let mut status_lines = 0;
+        for line in lines.iter().skip(self.status_scroll_offset).take(visible) {
+            self.go_to(term_x + 1, term_y + status_lines);
+            print!(self, line);
+            status_lines += 1;
         }
         self.status_box_height = status_lines;
     }
+    /// Scroll the status box upwards one line. It is redrawn every frame.
+    pub fn status_scroll_up(&mut self) {
+        self.status_scroll_offset = self.status_scroll_offset.saturating_sub(1);
+    }
+    /// Scroll the status box downwards one line. The offset is limited to
+    /// the length of the status box when it is next rendered.
+    pub fn status_scroll_down(&mut self) {
+        self.status_scroll_offset += 1;
+    }
+    /// Show or hide the coordinate rulers. Return whether they are now shown.
+    pub fn toggle_ruler(&mut self) -> bool {
+        self.ruler_enabled = !self.ruler_enabled;
+        if !self.ruler_enabled {
+            self.go_to(1, 1);
+            self.clear_line();
+            for vis_y in 0..self.view_height {
+                self.go_to(1, vis_y + 2);
+                print!(self, ' ');
+            }
+        }
+        self.ruler_enabled
+    }
+    /// Render the last two digits of the absolute column of each cell above
+    /// the view window, and the last digit of the absolute row of each cell
+    /// to its left. There is only room for a single digit on the left.
+    pub fn render_ruler(&mut self, grid_width: usize, grid_height: usize) {
+        if !self.ruler_enabled || self.overview_enabled {
+            return;
+        }
+        let (color, reset) = (self.fg(Color::LightGray), self.fg(Color::Reset));
+        for vis_x in 0..std::cmp::min(self.view_width as usize, grid_width) {
+            let x = (self.view_offset.x + vis_x) % grid_width;
+            self.go_to(vis_x as u16 * 3 + 3, 1);
+            print!(self, "{}{:02}{}", color, x % 100, reset);
+        }
+        for vis_y in 0..std::cmp::min(self.view_height as usize, grid_height) {
+            let y = (self.view_offset.y + vis_y) % grid_height;
+            self.go_to(1, vis_y as u16 + 2);
+            print!(self, "{}{}{}", color, y % 10, reset);
+        }
+    }
+    /// The terminal column at which the minimap starts, leaving room for the
+    /// status box.
+    fn minimap_x(&self) -> u16 {
+        self.view_width * 3 + 27
+    }
+    /// Show or hide the minimap. Return whether it is now shown.
+    pub fn toggle_minimap(&mut self) -> bool {
+        self.minimap_enabled = !self.minimap_enabled;
+        if self.minimap_enabled {
+            self.minimap_countdown = 0;
+        } else {
+            let term_x = self.minimap_x();
+            for i in 0..MINIMAP_SIZE as u16 {
+                self.go_to(term_x, 2 + i);
+                self.clear_right();
+            }
+        }
+        self.minimap_enabled
+    }
+    /// Render a downsampled version of the whole grid, with the cells that
+    /// overlap the view window marked. The colors are only recomputed every
+    /// few frames.
+    pub fn render_minimap<R>(&mut self, grid: &Grid<R>) {
+        if !self.minimap_enabled {
+            return;
+        }
+        let (width, height) = (grid.width(), grid.height());
+        let map_width = std::cmp::min(MINIMAP_SIZE, width);
+        let map_height = std::cmp::min(MINIMAP_SIZE, height);
+        if self.minimap_countdown == 0 || self.minimap.len() != map_width * map_height {
+            // Average the colors of the cells in each block.
+            let palette = self.palette.unwrap_or(Category::DEFAULT_PALETTE);
+            let mut sums = vec![[0u64; 3]; map_width * map_height];
+            let mut counts = vec![0u64; map_width * map_height];
+            for (p, byte) in grid.view_all() {
+                let idx = (p.y * map_height / height) * map_width + p.x * map_width / width;
+                let color = palette[Instruction::from_byte(byte).category() as usize];
+                for (sum, &component) in sums[idx].iter_mut().zip(&color) {
+                    *sum += component as u64;
+                }
+                counts[idx] += 1;
+            }
+            self.minimap = sums.iter().zip(&counts)
+                .map(|(sum, &n)| [(sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8])
+                .collect();
+            self.minimap_countdown = MINIMAP_REFRESH_FRAMES;
+        }
+        self.minimap_countdown -= 1;
+        let view_width = std::cmp::min(self.view_width as usize, width);
+        let view_height = std::cmp::min(self.view_height as usize, height);
+        let term_x = self.minimap_x();
+        for y in 0..map_height {
+            self.go_to(term_x, 2 + y as u16);
+            let in_view_y = overlaps_modular(
+                y * height / map_height,
+                (y + 1) * height / map_height,
+                self.view_offset.y, view_height, height);
+            for x in 0..map_width {
+                let in_view = in_view_y && overlaps_modular(
+                    x * width / map_width,
+                    (x + 1) * width / map_width,
+                    self.view_offset.x, view_width, width);
+                let bg = self.bg(Color::Rgb(self.minimap[y * map_width + x]));
+                let (fg, reset_fg, reset_bg) =
+                    (self.fg(Color::Yellow), self.fg(Color::Reset), self.bg(Color::Reset));
+                let cell = if in_view { "[]" } else { "  " };
+                print!(self, "{}{}{}{}{}", bg, fg, cell, reset_fg, reset_bg);
+            }
+        }
+    }
+    /// Hide or show instructions of a category. Return whether anything
+    /// changed.
+    pub fn set_hidden(&mut self, category: Category, hidden: bool) -> bool {
+        if hidden {
+            self.hidden_categories.insert(category)
+        } else {
+            self.hidden_categories.remove(&category)
+        }
+    }
+    /// Switch between showing the cells around the view offset and showing
+    /// an overview of the whole grid. Return whether the overview is now shown.
+    pub fn toggle_overview(&mut self) -> bool {
+        self.overview_enabled = !self.overview_enabled;
+        self.clear_view();
+        self.overview_enabled
+    }
+    /// Render the whole grid in the space of the view window, drawing each
+    /// block of cells as a single character colored by the most common
+    /// category of instruction in it. Blocks containing an IP are highlighted
+    /// as in the detailed view.
+    fn render_overview<R>(
+        &mut self,
+        grid: &Grid<R>,
+        focused_pos: Option<Point>,
+        occupied: HashSet<Point>,
+    ) {
+        let (width, height) = (grid.width(), grid.height());
+        let map_width = std::cmp::min(self.view_width as usize * 3 + 1, width);
+        let map_height = std::cmp::min(self.view_height as usize, height);
+        let block_of = |p: Point| (p.y * map_height / height) * map_width + p.x * map_width / width;
+        let mut counts = vec![[0u32; 7]; map_width * map_height];
+        for (p, byte) in grid.view_all() {
+            counts[block_of(p)][Instruction::from_byte(byte).category() as usize] += 1;
+        }
+        let mut highlights = vec![Color::None; map_width * map_height];
+        for p in occupied {
+            let highlight = &mut highlights[block_of(p)];
+            if focused_pos == Some(p) {
+                *highlight = Color::Yellow;
+            } else if let Color::None = highlight {
+                *highlight = Color::Blue;
+            }
+        }
+        for y in 0..map_height {
+            self.go_to(2, y as u16 + 2);
+            for x in 0..map_width {
+                let idx = y * map_width + x;
+                // Ties are broken in favor of the earlier category.
+                let (category, _) = Category::ALL.iter()
+                    .zip(&counts[idx])
+                    .rev()
+                    .max_by_key(|&(_, &n)| n)
+                    .unwrap();
+                let c = match category {
+                    Category::Special => '·',
+                    _ => '█',
+                };
+                let (bg, fg) = (self.bg(highlights[idx]), self.fg(self.category_color(*category)));
+                let (reset_fg, reset_bg) = (self.fg(Color::Reset), self.bg(Color::Reset));
+                print!(self, "{}{}{}{}{}", bg, fg, c, reset_fg, reset_bg);
+            }
+        }
+    }
     /// Render the colored cells in the grid.
     pub fn render_grid<R: Rng>(
         &mut self,
@@ -329,82 +842,151 @@ impl<W: Write> UI<W> {
         focused: Option<&OrganismState>,
         occupied: HashSet<Point>,
     ) {
-        // Determine the position of the focused organism and the points in
-        // the square that it is selecting.
-        let (focused_pos, selected) = match focused {
+        if self.overview_enabled {
+            self.render_overview(grid, focused.map(|state| state.ip), occupied);
+            return;
+        }
+        self.render_selection(grid.width(), grid.height());
+        // Determine the position of the focused organism, its cursor, and
+        // the points in the square that it is selecting.
+        let (focused_pos, focused_cursor, selected) = match focused {
             Some(state) => (
                 Some(state.ip),
+                Some(state.cursor),
                 get_points_for_selection(
                     state.cursor,
                     state.r,
                     grid
                 ).collect()
             ),
-            None => (None, HashSet::new()),
+            None => (None, None, HashSet::new()),
         };
-        // Get the points that should be viewed.
-        let view = grid.view(
-            self.view_offset,
-            self.view_width as usize,
-            self.view_height as usize
-        );
-        for (vis_y, row) in view.enumerate() {
-            for (vis_x, (pos, byte)) in row.enumerate() {
+        // Copy each row of the view out of the grid at once rather than
+        // looking up every cell.
+        let (width, height) = (grid.width(), grid.height());
+        let view_width = std::cmp::min(self.view_width as usize, width);
+        let view_height = std::cmp::min(self.view_height as usize, height);
+        for vis_y in 0..view_height {
+            let y = (self.view_offset.y + vis_y) % height;
+            let row = grid.row_slice(y, self.view_offset.x, view_width);
+            for (vis_x, &byte) in row.iter().enumerate() {
+                let pos = Point { x: (self.view_offset.x + vis_x) % width, y };
                 // Go to the correct position.
                 let term_x = (vis_x as u16) * 3 + 3;
                 let term_y = (vis_y as u16) + 2;
                 self.go_to(term_x, term_y);
                 // The focused IP is highlighted yellow; the focused organism's
-                // selection is highlighted red, and non-focused IPs are
-                // highlighted blue.
+                // cursor is highlighted green and the rest of its selection
+                // red, and non-focused IPs are highlighted blue.
                 let bg_color = if occupied.contains(&pos) {
                     if focused_pos == Some(pos) { Color::Yellow } else { Color::Blue }
+                } else if focused_cursor == Some(pos) {
+                    Color::LightGreen
                 } else if selected.contains(&pos) {
                     Color::Red
                 } else {
                     Color::None
                 };
                 let ins = Instruction::from_byte(byte);
-                let fg_color = ins.category().color();
+                // Bytes that don't correspond to an instruction act like
+                // `..`, but are drawn differently so they can be told apart.
+                let fg_color = if Instruction::is_defined(byte) {
+                    self.category_color(ins.category())
+                } else {
+                    Color::Red
+                };
                 // Write the instruction with the appropriate foreground and background colors.
-                print!(self, "{}{}{}{}{}",
-                    bg_color.bg(),
-                    fg_color.fg(),
-                    ins,
-                    Color::Reset.fg(),
-                    Color::Reset.bg()
-                );
+                let (bg, fg) = (self.bg(bg_color), self.fg(fg_color));
+                let (reset_fg, reset_bg) = (self.fg(Color::Reset), self.bg(Color::Reset));
+                if self.hidden_categories.contains(&ins.category()) {
+                    print!(self, "{}  {}", bg, reset_bg);
+                } else {
+                    print!(self, "{}{}{}{}{}", bg, fg, ins, reset_fg, reset_bg);
+                }
             }
         }
     }
     /// Display a command line that allows the user to enter a string.
-    pub fn input_command<R: Read>(
+    pub fn input_command(
         &mut self,
-        key_input: &mut termion::input::Keys<R>,
+        events: &mut dyn Iterator<Item = std::io::Result<termion::event::Event>>,
+        command_names: &[&str],
     ) -> Option<String> {
         let mut command = String::new();
-        let term_y = (self.view_height as u16) + 3;
-        let term_x = 2;
-        self.go_to(term_x, term_y);
-        self.clear_right();
+        // The index into the history of the command being displayed, or
+        // `None` if a new command is being typed.
+        let mut history_idx: Option<usize> = None;
+        // The new command being typed, saved while browsing the history.
+        let mut draft = String::new();
         self.show_cursor();
-        print!(self, ": ");
-        self.flush();
+        self.redraw_command(&command);
         loop {
-            if let Some(key) = key_input.next() {
-                use termion::event::Key;
-                match key.unwrap() {
+            if let Some(event) = events.next() {
+                use termion::event::{Event, Key};
+                let key = match event.unwrap() {
+                    Event::Key(key) => key,
+                    _ => continue,
+                };
+                match key {
                     Key::Char('\n') => {
                         self.hide_cursor();
                         self.flush();
+                        if !command.trim().is_empty() {
+                            self.command_history.push(command.clone());
+                        }
                         return Some(command);
                     }
+                    Key::Up if !self.command_history.is_empty() => {
+                        let idx = match history_idx {
+                            Some(idx) => idx.saturating_sub(1),
+                            None => {
+                                draft = command.clone();
+                                self.command_history.len() - 1
+                            }
+                        };
+                        history_idx = Some(idx);
+                        command = self.command_history[idx].clone();
+                        self.redraw_command(&command);
+                    }
+                    Key::Char('\t') if !command.contains(char::is_whitespace) => {
+                        let mut candidates: Vec<&str> = command_names.iter()
+                            .copied()
+                            .filter(|name| name.starts_with(command.as_str()))
+                            .collect();
+                        candidates.sort_unstable();
+                        if candidates.len() == 1 {
+                            command = format!("{} ", candidates[0]);
+                        } else if let Some(first) = candidates.first() {
+                            // Complete to the longest prefix shared by all candidates.
+                            let prefix_len = candidates.iter()
+                                .map(|name| first.bytes()
+                                    .zip(name.bytes())
+                                    .take_while(|(a, b)| a == b)
+                                    .count())
+                                .min()
+                                .unwrap_or(0);
+                            command = first[..prefix_len].to_string();
+                            self.info(candidates.iter().map(|&name| name.to_string()).collect());
+                        }
+                        self.redraw_command(&command);
+                    }
+                    Key::Char('\t') => {}
+                    Key::Down => if let Some(idx) = history_idx {
+                        if idx + 1 < self.command_history.len() {
+                            history_idx = Some(idx + 1);
+                            command = self.command_history[idx + 1].clone();
+                        } else {
+                            history_idx = None;
+                            command = std::mem::take(&mut draft);
+                        }
+                        self.redraw_command(&command);
+                    }
                     Key::Char(c) => {
                         command.push(c);
                         print!(self, c);
                         self.flush();
                     }
-                    Key::Backspace => if command.pop().is_some() {
+                    Key::Backspace if command.pop().is_some() => {
                         self.back();
                         print!(self, ' ');
                         self.back();
@@ -1,5 +1,7 @@
 use rand::Rng;
 
+use serde::Serialize;
+
 /// Sort a pair of values.
 fn min_max<T: Ord>(a: T, b: T) -> (T, T) {
     if a > b {
@@ -26,11 +28,13 @@ fn sub_modular(a: usize, b: usize, rem: usize) -> usize {
     }
 }
 
+#[derive(Clone, Serialize)]
 pub struct Grid<R> {
     width: usize,
     height: usize,
     // invariant: data.len() == width * height
     data: Vec<u8>,
+    #[serde(skip)]
     rng: R,
     /// The inverse probability of a cosmic ray occuring on a given cycle.
     /// This is set to 0 if the probability is 0.
@@ -38,6 +42,11 @@ pub struct Grid<R> {
     /// The inverse probability that an attempt to write to a wall will succeed.
     /// This is set to 0 if the probability is 0.
     pub wall_pierce_chance: u32,
+    /// Whether movement off one edge of the grid wraps around to the
+    /// opposite edge. If not, the edges act as walls.
+    pub wrap: bool,
+    /// Whether instructions that make organisms move diagonally are enabled.
+    pub diagonals: bool,
 }
 
 impl<R> Grid<R> {
@@ -57,6 +66,37 @@ impl<R> Grid<R> {
     pub fn get(&self, p: Point) -> Option<u8> {
         self.get_ref(p).copied()
     }
+    /// Determine whether moving a point one step in a direction would cross
+    /// an edge of the grid.
+    fn crosses_edge(&self, p: Point, dir: Dir) -> bool {
+        match dir {
+            Dir::L => p.x == 0,
+            Dir::R => p.x == self.width - 1,
+            Dir::U => p.y == 0,
+            Dir::D => p.y == self.height - 1,
+            Dir::UL => p.y == 0 || p.x == 0,
+            Dir::UR => p.y == 0 || p.x == self.width - 1,
+            Dir::DL => p.y == self.height - 1 || p.x == 0,
+            Dir::DR => p.y == self.height - 1 || p.x == self.width - 1,
+        }
+    }
+    /// Move a point one step in a direction, or return `None` if the grid is
+    /// bounded and the step would leave it.
+    pub fn move_in(&self, p: Point, dir: Dir) -> Option<Point> {
+        if !self.wrap && self.crosses_edge(p, dir) {
+            None
+        } else {
+            Some(p.move_in(dir, self.width, self.height))
+        }
+    }
+    /// Return the orthogonal neighbors of a point, excluding those across an
+    /// edge if the grid is bounded.
+    pub fn neighbors<'a>(&'a self, p: Point) -> impl Iterator<Item=Point> + 'a {
+        Dir::ORTHOGONAL.iter()
+            .zip(p.neighbors(self.width, self.height))
+            .filter(move |&(&dir, _)| self.wrap || !self.crosses_edge(p, dir))
+            .map(|(_, q)| q)
+    }
     pub fn view<'a>(&'a self, start: Point, width: usize, height: usize)
         -> impl Iterator<Item=impl Iterator<Item=(Point, u8)> + 'a> + 'a
     {
@@ -69,27 +109,50 @@ impl<R> Grid<R> {
             })
         })
     }
-    pub fn view_all<'a>(&'a self) -> impl Iterator<Item=u8> + 'a {
-        self.view(ORIGIN, self.width, self.height).flatten().map(|(_, ins)| ins)
+    /// Write `count` random values to random cells using `rng`. Unlike `set`,
+    /// this doesn't roll for write errors, since the values are already
+    /// random.
+    pub fn apply_random<G: Rng>(&mut self, count: u32, rng: &mut G) {
+        for _ in 0..count {
+            let idx = rng.gen_range(0, self.data.len());
+            self.data[idx] = rng.gen();
+        }
+    }
+    /// Return `len` consecutive cells of row `y` starting at column `x`,
+    /// wrapping around to the start of the row. `len` must be at most the
+    /// width of the grid.
+    pub fn row_slice(&self, y: usize, x: usize, len: usize) -> Vec<u8> {
+        assert!(y < self.height && x < self.width && len <= self.width);
+        let row = &self.data[y * self.width..(y + 1) * self.width];
+        let mut result = vec![0; len];
+        let first = std::cmp::min(len, self.width - x);
+        result[..first].copy_from_slice(&row[x..x + first]);
+        result[first..].copy_from_slice(&row[..len - first]);
+        result
+    }
+    pub fn view_all<'a>(&'a self) -> impl Iterator<Item=(Point, u8)> + 'a {
+        self.view(ORIGIN, self.width, self.height).flatten()
     }
 }
 
-impl<'a, R: Rng> Grid<R> {
+impl<R: Rng> Grid<R> {
+    /// Create a grid with every cell set to `fill`, except for 1 in every
+    /// `write_error_chance` cells which are set randomly. If `fill` is `None`,
+    /// every cell is set randomly.
     pub fn init(
         width: usize,
         height: usize,
         mut rng: R,
-        fill: u8,
+        fill: Option<u8>,
         write_error_chance: u32
     ) -> Self {
         assert_ne!(width * height, 0);
         let mut data = Vec::new();
         for _ in 0..width * height {
-            if write_error_chance != 0
-              && rng.gen_ratio(1, write_error_chance) {
-                data.push(rng.gen());
-            } else {
-                data.push(fill);
+            match fill {
+                Some(fill) if write_error_chance == 0
+                  || !rng.gen_ratio(1, write_error_chance) => data.push(fill),
+                _ => data.push(rng.gen()),
             }
         }
         Self {
@@ -98,33 +161,55 @@ impl<'a, R: Rng> Grid<R> {
             rng,
             write_error_chance,
             wall_pierce_chance: 0,
+            wrap: true,
+            diagonals: false,
         }
     }
+    /// Access the RNG used for write errors and wall piercing, so that
+    /// organisms can make random choices reproducibly.
+    pub fn rng_mut(&mut self) -> &mut R {
+        &mut self.rng
+    }
+    /// Replace the RNG used for write errors and wall piercing.
+    pub fn set_rng(&mut self, rng: R) {
+        self.rng = rng;
+    }
     pub fn pierce_wall(&mut self) -> bool {
         self.wall_pierce_chance != 0
             && self.rng.gen_ratio(1, self.wall_pierce_chance)
     }
-    pub fn set(&mut self, p: Point, new: u8) {
+    /// Write a value to the grid without any chance of a write error.
+    pub fn set_exact(&mut self, p: Point, new: u8) {
+        if p.x < self.width && p.y < self.height {
+            self.data[p.y * self.width + p.x] = new;
+        } else {
+            panic!("{:?} is out of bounds", p);
+        }
+    }
+    /// Write a value to the grid. Return whether a write error occurred, in
+    /// which case a random value was stored instead of `new`. The random value
+    /// can happen to equal `new`.
+    pub fn set(&mut self, p: Point, new: u8) -> bool {
         if p.x < self.width && p.y < self.height {
             let wrong = self.rng.gen();
-            self.data[p.y * self.width + p.x] =
-                if self.write_error_chance > 0
-                    && self.rng.gen_ratio(1, self.write_error_chance)
-                { wrong } else { new };
+            let error = self.write_error_chance > 0
+                && self.rng.gen_ratio(1, self.write_error_chance);
+            self.data[p.y * self.width + p.x] = if error { wrong } else { new };
+            error
         } else {
             panic!("{:?} is out of bounds", p);
         }
     }
 }
 
-impl<'a, R> std::ops::Index<Point> for Grid<R> {
+impl<R> std::ops::Index<Point> for Grid<R> {
     type Output = u8;
     fn index(&self, p: Point) -> &u8 {
         self.get_ref(p).unwrap()
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
 pub struct Point { pub x: usize, pub y: usize }
 
 pub const ORIGIN: Point = Point { x: 0, y: 0 };
@@ -136,6 +221,24 @@ impl Point {
             y: y.rem_euclid(height as isize) as usize,
         }
     }
+    /// Return the position of the point relative to a view window whose
+    /// top-left corner is `offset`, or `None` if it lies outside the window.
+    pub fn in_view(
+        self,
+        offset: Point,
+        view_width: usize,
+        view_height: usize,
+        grid_width: usize,
+        grid_height: usize,
+    ) -> Option<Self> {
+        let x = (self.x + grid_width - offset.x) % grid_width;
+        let y = (self.y + grid_height - offset.y) % grid_height;
+        if x < view_width && y < view_height {
+            Some(Self { x, y })
+        } else {
+            None
+        }
+    }
     pub fn up(self, height: usize) -> Self {
         assert!(self.y < height);
         let y = if self.y == 0 { height - 1 } else { self.y - 1 };
@@ -184,6 +287,10 @@ impl Point {
             Dir::R => self.right(width),
             Dir::U => self.up(height),
             Dir::D => self.down(height),
+            Dir::UL => self.up(height).left(width),
+            Dir::UR => self.up(height).right(width),
+            Dir::DL => self.down(height).left(width),
+            Dir::DR => self.down(height).right(width),
         }
     }
     pub fn move_in_n(self, dir: Dir, n: usize, width: usize, height: usize) -> Self {
@@ -192,9 +299,24 @@ impl Point {
             Dir::R => self.right_n(n, width),
             Dir::U => self.up_n(n, height),
             Dir::D => self.down_n(n, height),
+            Dir::UL => self.up_n(n, height).left_n(n, width),
+            Dir::UR => self.up_n(n, height).right_n(n, width),
+            Dir::DL => self.down_n(n, height).left_n(n, width),
+            Dir::DR => self.down_n(n, height).right_n(n, width),
         }
     }
-    /// Modular taxicab distance.
+    /// The four orthogonal neighbors of a point, in the same order as
+    /// `Dir::ORTHOGONAL`, wrapping around the edges.
+    pub fn neighbors(self, width: usize, height: usize) -> [Point; 4] {
+        [
+            self.up(height),
+            self.down(height),
+            self.left(width),
+            self.right(width),
+        ]
+    }
+    /// Modular Chebyshev distance (the larger of the distances along each axis).
+    /// This matches the square regions selected by organisms.
     pub fn dist_to(self, other: Point, width: usize, height: usize) -> usize {
         std::cmp::max(
             dist_modular(self.x, other.x, width),
@@ -209,16 +331,24 @@ impl Point {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
-pub enum Dir { L, R, U, D }
+/// A direction of movement. The diagonal directions are only used by
+/// organisms if they are enabled with `--diagonals`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize)]
+pub enum Dir { L, R, U, D, UL, UR, DL, DR }
 
 impl Dir {
+    /// The four orthogonal directions, in the order used by `Point::neighbors`.
+    pub const ORTHOGONAL: [Dir; 4] = [Dir::U, Dir::D, Dir::L, Dir::R];
     pub fn to_char(self) -> char {
         match self {
             Dir::L => '<',
             Dir::R => '>',
             Dir::U => '^',
             Dir::D => 'v',
+            Dir::UL => '\u{2196}',
+            Dir::UR => '\u{2197}',
+            Dir::DL => '\u{2199}',
+            Dir::DR => '\u{2198}',
         }
     }
     pub fn from_str(s: &str) -> Option<Self> {
@@ -227,6 +357,10 @@ impl Dir {
             ">" => Some(Dir::R),
             "^" => Some(Dir::U),
             "v" => Some(Dir::D),
+            "<^" | "\u{2196}" => Some(Dir::UL),
+            ">^" | "\u{2197}" => Some(Dir::UR),
+            "<v" | "\u{2199}" => Some(Dir::DL),
+            ">v" | "\u{2198}" => Some(Dir::DR),
             _ => None
         }
     }
@@ -237,6 +371,10 @@ impl Dir {
             Dir::R => Dir::L,
             Dir::U => Dir::D,
             Dir::D => Dir::U,
+            Dir::UL => Dir::DR,
+            Dir::UR => Dir::DL,
+            Dir::DL => Dir::UR,
+            Dir::DR => Dir::UL,
         }
     }
     /// Reflect as in '|'.
@@ -244,6 +382,10 @@ impl Dir {
         match self {
             Dir::L => Dir::R,
             Dir::R => Dir::L,
+            Dir::UL => Dir::UR,
+            Dir::UR => Dir::UL,
+            Dir::DL => Dir::DR,
+            Dir::DR => Dir::DL,
             d => d
         }
     }
@@ -252,6 +394,10 @@ impl Dir {
         match self {
             Dir::U => Dir::D,
             Dir::D => Dir::U,
+            Dir::UL => Dir::DL,
+            Dir::UR => Dir::DR,
+            Dir::DL => Dir::UL,
+            Dir::DR => Dir::UR,
             d => d
         }
     }
@@ -262,6 +408,9 @@ impl Dir {
             Dir::R => Dir::U,
             Dir::U => Dir::R,
             Dir::D => Dir::L,
+            Dir::UL => Dir::DR,
+            Dir::DR => Dir::UL,
+            d => d
         }
     }
     /// Reflect as in '\'.
@@ -271,6 +420,52 @@ impl Dir {
             Dir::R => Dir::D,
             Dir::U => Dir::L,
             Dir::D => Dir::R,
+            Dir::UR => Dir::DL,
+            Dir::DL => Dir::UR,
+            d => d
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng as _;
+    use rand::rngs::StdRng;
+
+    use super::*;
+
+    fn grid(width: usize, height: usize) -> Grid<StdRng> {
+        Grid::init(width, height, StdRng::seed_from_u64(0), Some(0), 0)
+    }
+
+    #[test]
+    fn set_reports_write_errors() {
+        let mut g = grid(4, 4);
+        g.write_error_chance = 1;
+        for x in 0..4 {
+            assert!(g.set(Point { x, y: 0 }, 7));
+        }
+        g.write_error_chance = 0;
+        for x in 0..4 {
+            assert!(!g.set(Point { x, y: 1 }, 7));
+            assert_eq!(g[Point { x, y: 1 }], 7);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn neighbors_wrap_around() {
+        let p = Point { x: 0, y: 0 };
+        assert_eq!(p.neighbors(5, 4), [
+            Point { x: 0, y: 3 },
+            Point { x: 0, y: 1 },
+            Point { x: 4, y: 0 },
+            Point { x: 1, y: 0 },
+        ]);
+
+        let mut g = grid(5, 4);
+        assert_eq!(g.neighbors(p).count(), 4);
+        g.wrap = false;
+        let bounded: Vec<_> = g.neighbors(p).collect();
+        assert_eq!(bounded, vec![Point { x: 0, y: 1 }, Point { x: 1, y: 0 }]);
+    }
+}
@@ -1,10 +1,12 @@
 use termion::cursor;
-use termion::raw::IntoRawMode as _;
+use termion::raw::{IntoRawMode as _, RawTerminal};
 use termion::input::TermRead;
 
 use structopt::StructOpt;
 
-use std::io;
+use std::io::{self, Stdout, Write as _};
+use std::panic;
+use std::sync::{Arc, Weak};
 
 /// Actual app state and rendering logic.
 mod app;
@@ -27,33 +29,81 @@ struct Options {
     view_width: u16,
     #[structopt(long="view-height", name="view height", default_value="35")]
     view_height: u16,
+    #[structopt(long="fill", name="fill instruction", default_value="..")]
+    fill: String,
     #[structopt(long="write-error-chance", name="initial write error chance", default_value="1")]
     write_error_chance: u32,
     #[structopt(long="seed", name="RNG seed")]
     rng_seed: Option<u64>,
+    #[structopt(long="bounded")]
+    bounded: bool,
+    #[structopt(long="diagonals")]
+    diagonals: bool,
+    #[structopt(long="no-color")]
+    no_color: bool,
     #[structopt(long="profile")]
     ignore_io: bool,
     #[structopt(name="initialization file")]
     initial_file: Option<String>,
 }
 
+/// The escape sequence that `MouseTerminal` writes to stop reporting mouse
+/// events, which termion doesn't expose.
+const EXIT_MOUSE_SEQUENCE: &str = "\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+
+/// Keeps the terminal in raw mode until it is dropped.
+struct TerminalGuard(RawTerminal<Stdout>);
+
+impl TerminalGuard {
+    fn new() -> io::Result<Self> {
+        io::stdout().into_raw_mode().map(TerminalGuard)
+    }
+    /// Put the terminal back into the state it was in before the program
+    /// started, without waiting for everything to be dropped.
+    fn restore(&self) {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "{}{}{}",
+            EXIT_MOUSE_SEQUENCE,
+            termion::screen::ToMainScreen,
+            cursor::Show);
+        let _ = stdout.flush();
+        let _ = self.0.suspend_raw_mode();
+    }
+}
+
+/// Make panic messages readable by restoring the terminal before the
+/// default hook prints them.
+fn install_panic_hook(guard: Weak<TerminalGuard>) {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Some(guard) = guard.upgrade() {
+            guard.restore();
+        }
+        default(info);
+    }));
+}
+
 fn main() {
     let options = Options::from_args();
     let ignore_io = options.ignore_io;
 
-    let stdout = io::stdout();
+    // This is dropped after the app, which leaves raw mode.
+    let _guard;
     let stdout = if ignore_io {
         None
     } else {
-        let stdout = stdout.into_raw_mode().unwrap();
-        let stdout = termion::screen::AlternateScreen::from(stdout);
+        let guard = Arc::new(TerminalGuard::new().unwrap());
+        install_panic_hook(Arc::downgrade(&guard));
+        _guard = guard;
+        let stdout = termion::screen::AlternateScreen::from(io::stdout());
         let stdout = cursor::HideCursor::from(stdout);
+        let stdout = termion::input::MouseTerminal::from(stdout);
         Some(stdout)
     };
     
     match app::AppState::init(options, stdout) {
         Ok(mut app) => if !ignore_io {
-            app.run(termion::async_stdin().keys())
+            app.run(termion::async_stdin().events())
         } else {
             let num_organisms = app.num_organisms();
             eprintln!("Ended with {} organism{}.",